@@ -0,0 +1,19 @@
+use std::fmt::Debug;
+use std::time::SystemTime;
+
+/// A command that changes the state of the module, as reported to an [`Audit`] hook.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandRecord {
+	/// The command line as sent, without the line ending.
+	pub command: String,
+	pub at: SystemTime,
+}
+
+/// Receives every state-changing command a gateway sends, for an audit trail.
+///
+/// Relay writes, output changes, links, event and configuration changes are reported;
+/// reads are not. The hook runs on the caller's task right before the command goes out,
+/// so it sees attempts that fail as well, and should return quickly.
+pub trait Audit: Debug + Send + Sync {
+	fn on_command(&self, record: &CommandRecord);
+}
@@ -0,0 +1,636 @@
+use crate::Error;
+use crate::Event;
+use crate::EventKind;
+use crate::EventReceiver;
+use crate::Gateway;
+use crate::InputLine;
+use crate::Relay;
+use crate::RelayLink;
+use crate::RelayRecord;
+use crate::Result;
+use crate::Signal;
+use crate::ADC_COUNT;
+use crate::INPUT_COUNT;
+use crate::RELAY_COUNT;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Client-side view of a module that remembers what its relays have been doing.
+///
+/// Relays obtained through [`Board::relay`] share one record per relay line, so every
+/// command issued through any of them updates the same cache. So do [`Event::Rele`]
+/// events, once turned on with [`Gateway::cfg_event`].
+#[derive(Debug, Clone)]
+pub struct Board {
+	gw: Arc<dyn Gateway + Send + Sync + 'static>,
+	relays: Arc<Records>,
+	snapshot_tier: Arc<Mutex<SnapshotTier>>,
+}
+
+/// What a [`Board`] knows about each relay line.
+type Records = Mutex<HashMap<u32, Arc<Mutex<RelayRecord>>>>;
+
+/// Time given a relay to switch before [`Board::verify_wiring`] reads the feedback input.
+const WIRING_SETTLE: Duration = Duration::from_millis(20);
+
+/// How [`Board::snapshot`] reads the module, from the most to the least efficient.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+enum SnapshotTier {
+	#[default]
+	Combined,
+	Batch,
+	PerChannel,
+}
+
+impl Board {
+	/// Must be called within a Tokio runtime, which runs the task following relay events.
+	pub fn new(gw: Arc<dyn Gateway + Send + Sync + 'static>) -> Self {
+		let relays = Arc::default();
+		tokio::spawn(track_relays(gw.subscibe(), Arc::downgrade(&relays)));
+
+		Self {
+			gw,
+			relays,
+			snapshot_tier: Default::default(),
+		}
+	}
+
+	pub fn relay(&self, line: u32) -> Relay {
+		Relay::with_record(self.gw.clone(), line, self.record(line))
+	}
+
+	pub fn input(&self, line: u32) -> InputLine {
+		InputLine::new(self.gw.clone(), line)
+	}
+
+	/// Time since the relay last changed state.
+	///
+	/// The device is asked first. Firmware that doesn't track it rejects the command, in which
+	/// case the answer comes from the changes this board has seen, and is `None` if there
+	/// were none yet.
+	pub async fn relay_last_change(&self, relay: u32) -> Result<Option<Duration>> {
+		match self.gw.relay_last_change(relay).await {
+			Ok(elapsed) => Ok(Some(elapsed)),
+			Err(Error::Unsupported | Error::SyntaxError(_)) => {
+				Ok(self.record(relay).lock().unwrap().since_change())
+			}
+			Err(err) => Err(err),
+		}
+	}
+
+	/// Number of times the relay has switched, to spot relays nearing the end of their
+	/// mechanical life.
+	///
+	/// The device's own counter is used if the firmware keeps one. Otherwise this counts the
+	/// changes this board has seen since it was created: those made by its own commands,
+	/// and those reported by [`Event::Rele`]. A command that leaves the relay as it was
+	/// doesn't count.
+	pub async fn relay_cycle_count(&self, relay: u32) -> Result<u64> {
+		match self.gw.relay_cycle_count(relay).await {
+			Err(Error::Unsupported | Error::SyntaxError(_)) => {
+				Ok(self.record(relay).lock().unwrap().cycles())
+			}
+			res => res,
+		}
+	}
+
+	/// Commissioning self-test for a relay wired back to an input.
+	///
+	/// Switches the relay to the opposite state and back, checking each time that
+	/// `feedback_line` reads `expected` while the relay is on and the other level while it
+	/// is off. Returns whether the wiring behaved; the relay ends up as it was.
+	pub async fn verify_wiring(
+		&self,
+		relay: u32,
+		feedback_line: u32,
+		expected: Signal,
+	) -> Result<bool> {
+		let was_on = self.gw.relay_status(relay).await?;
+
+		let mut wired = true;
+		for on in [!was_on, was_on] {
+			self.relay(relay).set(on).await?;
+			tokio::time::sleep(WIRING_SETTLE).await;
+			wired &= (self.gw.line_signal(feedback_line).await? == expected) == on;
+		}
+
+		Ok(wired)
+	}
+
+	/// Reads every relay, input line and ADC channel.
+	///
+	/// Uses the single-frame status if the firmware has it, then the batch reads, then one
+	/// command per channel on the oldest firmware. The first snapshot finds out which of these
+	/// works; later ones go straight to it. Only `#ERR` without a code, or
+	/// [`Error::Unsupported`], rules a way out; other rejections, e.g.
+	/// [`Rejection::Busy`](crate::Rejection::Busy), are returned.
+	pub async fn snapshot(&self) -> Result<BoardSnapshot> {
+		loop {
+			let tier = *self.snapshot_tier.lock().unwrap();
+			let res = match tier {
+				SnapshotTier::Combined => self.gw.status_snapshot().await,
+				SnapshotTier::Batch => self.batch_snapshot().await,
+				SnapshotTier::PerChannel => return self.per_channel_snapshot().await,
+			};
+
+			match res {
+				Err(Error::Unsupported | Error::SyntaxError(_)) => {
+					let mut current = self.snapshot_tier.lock().unwrap();
+					*current = (*current).max(match tier {
+						SnapshotTier::Combined => SnapshotTier::Batch,
+						_ => SnapshotTier::PerChannel,
+					});
+				}
+				res => return res,
+			}
+		}
+	}
+
+	async fn batch_snapshot(&self) -> Result<BoardSnapshot> {
+		Ok(BoardSnapshot {
+			relays: self.gw.read_all_relays().await?,
+			inputs: self.gw.read_all_inputs().await?,
+			adc: self.gw.adc_all().await?,
+		})
+	}
+
+	async fn per_channel_snapshot(&self) -> Result<BoardSnapshot> {
+		let mut snapshot = BoardSnapshot::default();
+		for relay in 1..=RELAY_COUNT {
+			snapshot.relays.push(self.gw.relay_status(relay).await?);
+		}
+		for line in 1..=INPUT_COUNT {
+			snapshot.inputs.push(self.gw.line_signal(line).await?);
+		}
+		for channel in 1..=ADC_COUNT {
+			snapshot.adc.push(self.gw.read_adc(channel).await?);
+		}
+
+		Ok(snapshot)
+	}
+
+	fn record(&self, line: u32) -> Arc<Mutex<RelayRecord>> {
+		self.relays.lock().unwrap().entry(line).or_default().clone()
+	}
+}
+
+/// Feeds [`Event::Rele`] into the records of a [`Board`] until the board or the event
+/// channel is gone.
+async fn track_relays(mut sub: EventReceiver, relays: Weak<Records>) {
+	loop {
+		match sub.recv().await {
+			Ok(Event::Rele { relay, state }) => {
+				let Some(relays) = relays.upgrade() else {
+					break;
+				};
+				let record = relays.lock().unwrap().entry(relay).or_default().clone();
+				record.lock().unwrap().switched(state);
+			}
+			Ok(_) | Err(RecvError::Lagged(_)) => (),
+			Err(RecvError::Closed) => break,
+		}
+	}
+}
+
+/// State of a module's lines at one point in time, as read by [`Board::snapshot`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BoardSnapshot {
+	/// Relay states, relay 1 first.
+	pub relays: Vec<bool>,
+	/// Input levels, line 1 first.
+	pub inputs: Vec<Signal>,
+	/// Raw ADC values, channel 1 first.
+	pub adc: Vec<u16>,
+}
+
+/// Desired configuration of a module, applied with
+/// [`Gateway::provision`](crate::Gateway::provision).
+///
+/// With the `serde` feature it can be kept in a file. Events are written as
+/// `{ kind, enabled }` entries and links as `{ relay, line, invert }`, with `line` left out
+/// for a host-controlled relay.
+///
+/// Input modes and network settings have no typed commands; they are part of the text
+/// configuration restored with [`Gateway::import_config`](crate::Gateway::import_config).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BoardConfig {
+	/// Password the module asks for from now on. `None` leaves it as it is.
+	#[cfg_attr(
+		feature = "serde",
+		serde(default, skip_serializing_if = "Option::is_none")
+	)]
+	pub password: Option<String>,
+	/// Event kinds to enable (`true`) or disable (`false`).
+	#[cfg_attr(feature = "serde", serde(default, with = "schema::events"))]
+	pub events: Vec<(EventKind, bool)>,
+	/// Relays that follow an input by themselves (`Some`) or are host-controlled (`None`).
+	#[cfg_attr(feature = "serde", serde(default, with = "schema::links"))]
+	pub links: Vec<(u32, Option<RelayLink>)>,
+	/// Relay states, relay 1 first.
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub relays: Vec<bool>,
+}
+
+/// File layout of [`BoardConfig`]. Tuples and `None` inside lists can't be written as TOML,
+/// so entries get named fields instead.
+#[cfg(feature = "serde")]
+mod schema {
+	use crate::EventKind;
+	use crate::RelayLink;
+	use serde::Deserialize;
+	use serde::Deserializer;
+	use serde::Serialize;
+	use serde::Serializer;
+
+	#[derive(Serialize, Deserialize)]
+	struct EventEntry {
+		kind: EventKind,
+		enabled: bool,
+	}
+
+	#[derive(Serialize, Deserialize)]
+	struct LinkEntry {
+		relay: u32,
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		line: Option<u32>,
+		#[serde(default)]
+		invert: bool,
+	}
+
+	pub mod events {
+		use super::*;
+
+		pub fn serialize<S>(events: &[(EventKind, bool)], ser: S) -> Result<S::Ok, S::Error>
+		where
+			S: Serializer,
+		{
+			ser.collect_seq(
+				events
+					.iter()
+					.map(|&(kind, enabled)| EventEntry { kind, enabled }),
+			)
+		}
+
+		pub fn deserialize<'de, D>(de: D) -> Result<Vec<(EventKind, bool)>, D::Error>
+		where
+			D: Deserializer<'de>,
+		{
+			let entries = Vec::<EventEntry>::deserialize(de)?;
+			Ok(
+				entries
+					.into_iter()
+					.map(|entry| (entry.kind, entry.enabled))
+					.collect(),
+			)
+		}
+	}
+
+	pub mod links {
+		use super::*;
+
+		pub fn serialize<S>(links: &[(u32, Option<RelayLink>)], ser: S) -> Result<S::Ok, S::Error>
+		where
+			S: Serializer,
+		{
+			ser.collect_seq(links.iter().map(|(relay, link)| LinkEntry {
+				relay: *relay,
+				line: link.as_ref().map(|link| link.line),
+				invert: link.as_ref().is_some_and(|link| link.invert),
+			}))
+		}
+
+		pub fn deserialize<'de, D>(de: D) -> Result<Vec<(u32, Option<RelayLink>)>, D::Error>
+		where
+			D: Deserializer<'de>,
+		{
+			let entries = Vec::<LinkEntry>::deserialize(de)?;
+			Ok(
+				entries
+					.into_iter()
+					.map(|entry| {
+						let link = entry.line.map(|line| RelayLink {
+							line,
+							invert: entry.invert,
+						});
+						(entry.relay, link)
+					})
+					.collect(),
+			)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::scripted;
+	use crate::ClickDelay;
+	use crate::StreamGateway;
+
+	#[tokio::test]
+	async fn board_tracks_relay_changes() -> Result<()> {
+		let (gw, _) = scripted(|cmd| match cmd {
+			"$KE,REL,2,1" => "#REL,OK".into(),
+			_ => "#ERR".into(),
+		});
+		let board = Board::new(Arc::new(gw));
+
+		assert_eq!(board.relay_last_change(2).await?, None);
+
+		board.relay(2).on().await?;
+		let elapsed = board.relay_last_change(2).await?.unwrap();
+		assert!(elapsed < Duration::from_secs(1));
+		assert_eq!(board.relay_last_change(1).await?, None);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn board_counts_relay_cycles() -> Result<()> {
+		let (gw, _) = scripted(|cmd| match cmd {
+			"$KE,REL,1,1" | "$KE,REL,1,0" | "$KE,REL,1,2" => "#REL,OK".into(),
+			"$KE,RCC,2" => "#RCC,2,5000".into(),
+			_ => "#ERR".into(),
+		});
+		let board = Board::new(Arc::new(gw));
+
+		let relay = board.relay(1);
+		relay.on().await?;
+		relay.off().await?;
+		board.relay(1).toggle().await?;
+		assert!(board.relay(1).click(ClickDelay::Seconds(1)).await.is_err());
+		assert_eq!(board.relay_cycle_count(1).await?, 3);
+
+		assert_eq!(board.relay_cycle_count(2).await?, 5000);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn board_tracks_relay_events() -> Result<()> {
+		let (gw, _) = scripted(|cmd| match cmd {
+			// Another client switches relay 2 on, and off again.
+			"$KE" => ["#M,RELE,2,1", "#M,RELE,2,1", "#M,RELE,2,0", "#OK"].join("\r\n"),
+			"$KE,REL,2,0" => "#REL,OK".into(),
+			_ => "#ERR".into(),
+		});
+		let gw = Arc::new(gw);
+		let board = Board::new(gw.clone());
+		assert_eq!(board.relay_last_change(2).await?, None);
+
+		gw.ping().await?;
+		tokio::time::timeout(Duration::from_secs(1), async {
+			while board.relay_cycle_count(2).await? < 2 {
+				tokio::task::yield_now().await;
+			}
+			Ok::<_, Error>(())
+		})
+		.await
+		.unwrap()?;
+		let elapsed = board.relay_last_change(2).await?.unwrap();
+		assert!(elapsed < Duration::from_secs(1));
+
+		// Already off, as the event said.
+		board.relay(2).off().await?;
+		assert_eq!(board.relay_cycle_count(2).await?, 2);
+
+		Ok(())
+	}
+
+	/// A device with relay 1 wired to input 3, or to nothing unless `wired`.
+	fn feedback_device(wired: bool) -> (StreamGateway, crate::testing::Received) {
+		let relay = Mutex::new(false);
+		scripted(move |cmd| {
+			let mut relay = relay.lock().unwrap();
+			match cmd {
+				"$KE,REL,1,1" | "$KE,REL,1,0" => {
+					*relay = cmd.ends_with('1');
+					"#REL,OK".into()
+				}
+				"$KE,RDR,1" => format!("#RDR,1,{}", u8::from(*relay)),
+				"$KE,RD,3" => format!("#RD,3,{}", u8::from(*relay && wired)),
+				_ => "#ERR".into(),
+			}
+		})
+	}
+
+	#[tokio::test]
+	async fn verify_wiring() -> Result<()> {
+		let (gw, received) = feedback_device(true);
+		let board = Board::new(Arc::new(gw));
+		assert!(board.verify_wiring(1, 3, Signal::High).await?);
+		assert!(!board.verify_wiring(1, 3, Signal::Low).await?);
+		assert_eq!(
+			received.lock().unwrap()[..5],
+			[
+				"$KE,RDR,1",
+				"$KE,REL,1,1",
+				"$KE,RD,3",
+				"$KE,REL,1,0",
+				"$KE,RD,3"
+			]
+		);
+
+		let (gw, _) = feedback_device(false);
+		let board = Board::new(Arc::new(gw));
+		assert!(!board.verify_wiring(1, 3, Signal::High).await?);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn snapshot_per_channel() -> Result<()> {
+		let (gw, received) = scripted(|cmd| match cmd {
+			"$KE,RDR,1" | "$KE,RDR,3" => format!("#RDR,{},1", &cmd[8..]),
+			"$KE,RDR,2" | "$KE,RDR,4" => format!("#RDR,{},0", &cmd[8..]),
+			"$KE,RD,2" => "#RD,2,1".into(),
+			cmd if cmd.starts_with("$KE,RD,") && !cmd.ends_with("ALL") => {
+				format!("#RD,{},0", &cmd[7..])
+			}
+			"$KE,ADC,1" => "#ADC,1,512".into(),
+			"$KE,ADC,2" => "#ADC,2,7".into(),
+			_ => "#ERR".into(),
+		});
+		let board = Board::new(Arc::new(gw));
+
+		let expected = BoardSnapshot {
+			relays: vec![true, false, true, false],
+			inputs: vec![
+				Signal::Low,
+				Signal::High,
+				Signal::Low,
+				Signal::Low,
+				Signal::Low,
+				Signal::Low,
+			],
+			adc: vec![512, 7],
+		};
+		assert_eq!(board.snapshot().await?, expected);
+		assert_eq!(received.lock().unwrap()[0], "$KE,ST");
+
+		received.lock().unwrap().clear();
+		assert_eq!(board.snapshot().await?, expected);
+		assert_eq!(received.lock().unwrap().len(), 12);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn snapshot_combined() -> Result<()> {
+		let (gw, received) = scripted(|cmd| match cmd {
+			"$KE,ST" => "#ST,0100,100000,3,4".into(),
+			_ => "#ERR".into(),
+		});
+		let board = Board::new(Arc::new(gw));
+
+		let snapshot = board.snapshot().await?;
+		assert_eq!(snapshot.relays, vec![false, true, false, false]);
+		assert_eq!(snapshot.inputs[0], Signal::High);
+		assert_eq!(snapshot.adc, vec![3, 4]);
+		assert_eq!(*received.lock().unwrap(), vec!["$KE,ST"]);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn snapshot_busy() -> Result<()> {
+		let busy = Mutex::new(true);
+		let (gw, received) = scripted(move |cmd| match cmd {
+			"$KE,ST" if std::mem::replace(&mut *busy.lock().unwrap(), false) => "#ERR,BSY".into(),
+			"$KE,ST" => "#ST,0100,100000,3,4".into(),
+			_ => "#ERR".into(),
+		});
+		let board = Board::new(Arc::new(gw));
+
+		assert!(matches!(
+			board.snapshot().await,
+			Err(Error::Rejected(crate::Rejection::Busy))
+		));
+		assert_eq!(board.snapshot().await?.adc, vec![3, 4]);
+		assert_eq!(*received.lock().unwrap(), ["$KE,ST", "$KE,ST"]);
+
+		Ok(())
+	}
+
+	#[cfg(feature = "serde")]
+	#[tokio::test]
+	async fn config_round_trip() -> Result<()> {
+		let json = serde_json::json!({
+			"events": [{ "kind": "EIN", "enabled": true }, { "kind": "1WT", "enabled": true }],
+			"links": [
+				{ "relay": 1, "line": 2, "invert": true },
+				{ "relay": 2, "invert": false },
+				{ "relay": 3, "invert": false },
+				{ "relay": 4, "invert": false },
+			],
+			"relays": [false, true, false, true],
+		});
+		let cfg: BoardConfig = serde_json::from_value(json.clone()).unwrap();
+		assert_eq!(
+			cfg.links[0].1,
+			Some(RelayLink {
+				line: 2,
+				invert: true
+			})
+		);
+
+		let device = Mutex::new((vec![false; 4], vec!["OFF".to_owned(); 4]));
+		let (gw, _) = scripted(move |cmd| {
+			let (relays, links) = &mut *device.lock().unwrap();
+			let parts: Vec<_> = cmd.split(',').collect();
+			let index = |relay: &str| relay.parse::<usize>().unwrap() - 1;
+			match parts[1..] {
+				["MSG", "S", _, "SET", _] => "#MSG,SET,OK".into(),
+				["LNK", relay, "OFF"] => {
+					links[index(relay)] = "OFF".into();
+					"#LNK,OK".into()
+				}
+				["LNK", relay, line, invert] => {
+					links[index(relay)] = format!("{line},{invert}");
+					"#LNK,OK".into()
+				}
+				["LNK", relay] => format!("#LNK,{relay},{}", links[index(relay)]),
+				["RDR", "ALL"] => "#ERR".into(),
+				["RDR", relay] => format!("#RDR,{relay},{}", u8::from(relays[index(relay)])),
+				["REL", "ALL", _] => "#ERR".into(),
+				["REL", relay, state] => {
+					relays[index(relay)] = state == "1";
+					"#REL,OK".into()
+				}
+				["SAVE"] => "#SAVE,OK".into(),
+				_ => "#ERR".into(),
+			}
+		});
+
+		gw.provision(&cfg).await?;
+		let running = gw.running_config().await?;
+		assert_eq!(running, cfg);
+		assert_eq!(serde_json::to_value(&running).unwrap(), json);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn provision() -> Result<()> {
+		let (gw, received) = scripted(|cmd| match cmd {
+			"$KE,PSW,NEW,secret" => "#PSW,NEW,OK".into(),
+			"$KE,MSG,S,EIN,SET,ON" => "#MSG,SET,OK".into(),
+			"$KE,LNK,1,2,0" => "#LNK,OK".into(),
+			"$KE,RDR,1" => "#RDR,1,0".into(),
+			"$KE,REL,ALL,1xxx" => "#REL,OK".into(),
+			"$KE,SAVE" => "#SAVE,OK".into(),
+			_ => "#ERR".into(),
+		});
+
+		let mut cfg = BoardConfig {
+			password: Some("secret".into()),
+			events: vec![(EventKind::Ein, true)],
+			links: vec![(
+				1,
+				Some(RelayLink {
+					line: 2,
+					invert: false,
+				}),
+			)],
+			relays: vec![true],
+		};
+		gw.provision(&cfg).await?;
+		assert_eq!(
+			*received.lock().unwrap(),
+			[
+				"$KE,PSW,NEW,secret",
+				"$KE,MSG,S,EIN,SET,ON",
+				"$KE,LNK,1,2,0",
+				"$KE,RDR,ALL",
+				"$KE,RDR,1",
+				"$KE,REL,ALL,1xxx",
+				"$KE,SAVE"
+			]
+		);
+
+		cfg.events.push((EventKind::Pwm, true));
+		match gw.provision(&cfg).await {
+			Err(Error::Provision { step, source }) => {
+				assert_eq!(step, "event PWM");
+				assert!(matches!(*source, Error::SyntaxError(_)));
+			}
+			res => panic!("Unexpected result: {res:?}"),
+		}
+
+		cfg.password = Some("other".into());
+		match gw.provision(&cfg).await {
+			Err(Error::Provision { step, source }) => {
+				assert_eq!(step, "password");
+				assert!(matches!(*source, Error::SyntaxError(_)));
+			}
+			res => panic!("Unexpected result: {res:?}"),
+		}
+
+		Ok(())
+	}
+}
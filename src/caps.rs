@@ -0,0 +1,83 @@
+/// Optional firmware features the gateway may rely on.
+///
+/// Laurent firmware revisions differ in which commands they understand. Features that are
+/// not declared here are emulated with the basic command set where possible.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+	/// `$KE,ADC,DIF,<pos>,<neg>` converts a differential channel pair natively.
+	pub adc_differential: bool,
+}
+
+impl Capabilities {
+	/// Derives the capabilities from the command listing returned by
+	/// [`Gateway::supported_commands`](crate::Gateway::supported_commands).
+	pub fn from_commands<S>(commands: &[S]) -> Self
+	where
+		S: AsRef<str>,
+	{
+		let has = |name: &str| commands.iter().any(|cmd| cmd.as_ref() == name);
+
+		Self {
+			adc_differential: has("ADC DIF"),
+		}
+	}
+}
+
+/// Firmware identification as reported by `$KE,INF`, for auditing a fleet of modules.
+///
+/// Older firmware only reports the model, so everything after it is optional.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FirmwareInfo {
+	pub model: String,
+	pub version: Option<String>,
+	pub build_date: Option<String>,
+	pub checksum: Option<String>,
+}
+
+impl FirmwareInfo {
+	/// Parses the fields of an `#INF` reply following the header:
+	/// `<model>[,<version>[,<build date>[,<checksum>]]]`. Empty fields count as absent.
+	pub fn from_parts<S>(parts: &[S]) -> Self
+	where
+		S: AsRef<str>,
+	{
+		let field = |index: usize| {
+			parts
+				.get(index)
+				.map(|part| part.as_ref().trim())
+				.filter(|part| !part.is_empty())
+				.map(ToOwned::to_owned)
+		};
+
+		Self {
+			model: field(0).unwrap_or_default(),
+			version: field(1),
+			build_date: field(2),
+			checksum: field(3),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn firmware_info_from_parts() {
+		let info = FirmwareInfo::from_parts(&["Laurent-2", "2.14", "2023-05-11", "A3F09C21"]);
+		assert_eq!(
+			info,
+			FirmwareInfo {
+				model: "Laurent-2".into(),
+				version: Some("2.14".into()),
+				build_date: Some("2023-05-11".into()),
+				checksum: Some("A3F09C21".into()),
+			}
+		);
+
+		let info = FirmwareInfo::from_parts(&["Laurent-2", "", "2023-05-11"]);
+		assert_eq!(info.version, None);
+		assert_eq!(info.build_date.as_deref(), Some("2023-05-11"));
+		assert_eq!(info.checksum, None);
+	}
+}
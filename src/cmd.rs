@@ -0,0 +1,80 @@
+use crate::codec::JoinParts;
+use std::fmt::Display;
+
+/// A `$KE` command assembled from a variable number of parts.
+///
+/// Sent with [`StreamGateway::send_command`](crate::StreamGateway::send_command).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+	parts: Vec<String>,
+}
+
+impl Command {
+	pub fn builder() -> CommandBuilder {
+		CommandBuilder {
+			parts: vec!["$KE".to_owned()],
+		}
+	}
+
+	/// The parts of the command, `$KE` included.
+	pub fn parts(&self) -> &[String] {
+		&self.parts
+	}
+}
+
+impl JoinParts for Command {
+	fn join_parts(&self) -> String {
+		self.parts.join_parts()
+	}
+}
+
+/// Collects the parts of a [`Command`] following the `$KE` prefix.
+#[derive(Debug, Clone)]
+pub struct CommandBuilder {
+	parts: Vec<String>,
+}
+
+impl CommandBuilder {
+	pub fn push(mut self, part: impl Display) -> Self {
+		self.parts.push(part.to_string());
+		self
+	}
+
+	pub fn extend<I>(mut self, parts: I) -> Self
+	where
+		I: IntoIterator,
+		I::Item: Display,
+	{
+		self
+			.parts
+			.extend(parts.into_iter().map(|part| part.to_string()));
+		self
+	}
+
+	pub fn build(self) -> Command {
+		Command { parts: self.parts }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::RelayAction;
+
+	#[test]
+	fn command_matches_tuple() {
+		let cmd = Command::builder()
+			.push("REL")
+			.push(2)
+			.push(RelayAction::On)
+			.build();
+		assert_eq!(
+			cmd.join_parts(),
+			("$KE", "REL", 2, RelayAction::On).join_parts()
+		);
+
+		let cmd = Command::builder().extend(["REL", "ALL", "1x0x"]).build();
+		assert_eq!(cmd.join_parts(), "$KE,REL,ALL,1x0x");
+		assert_eq!(Command::builder().build().join_parts(), "$KE");
+	}
+}
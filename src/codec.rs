@@ -5,8 +5,10 @@ use std::convert::identity;
 use std::fmt::Display;
 use std::io;
 use std::str;
+use tokio::sync::broadcast;
 use tokio_util::bytes::Buf;
 use tokio_util::bytes::BufMut;
+use tokio_util::bytes::Bytes;
 use tokio_util::bytes::BytesMut;
 use tokio_util::codec::Decoder;
 use tokio_util::codec::Encoder;
@@ -55,6 +57,15 @@ where
 	}
 }
 
+impl<T> JoinParts for Vec<T>
+where
+	T: Serke,
+{
+	fn join_parts(&self) -> String {
+		self.as_slice().join_parts()
+	}
+}
+
 macro_rules! join_parts_impl {
   ($($ty:ident),*) => {
     impl<$($ty),*> JoinParts for ($($ty),*,) where $($ty: Serke + Send + 'static),* {
@@ -82,6 +93,7 @@ pub struct Codec {
 	next_index: usize,
 	max_length: usize,
 	is_discarding: bool,
+	tap: Option<broadcast::Sender<Bytes>>,
 }
 
 impl Codec {
@@ -90,8 +102,21 @@ impl Codec {
 			next_index: 0,
 			max_length: 1024,
 			is_discarding: false,
+			tap: None,
 		}
 	}
+
+	/// Publishes every decoded line to `tap` exactly as received, line ending included.
+	pub fn with_tap(mut self, tap: broadcast::Sender<Bytes>) -> Self {
+		self.tap = Some(tap);
+		self
+	}
+}
+
+impl Default for Codec {
+	fn default() -> Self {
+		Self::new()
+	}
 }
 
 fn utf8(buf: &[u8]) -> Result<&str, io::Error> {
@@ -138,14 +163,20 @@ impl Decoder for Codec {
 					let newline_index = offset + self.next_index;
 					self.next_index = 0;
 					let line = src.split_to(newline_index + 1);
+					if let Some(tap) = &self.tap {
+						let _ = tap.send(Bytes::copy_from_slice(&line));
+					}
 					let line = &line[..line.len() - 1];
 					let line = without_carriage_return(line);
 					let line = utf8(line)?;
 					return Ok(Some(line.split(',').map(ToOwned::to_owned).collect()));
 				}
 				(false, None) if src.len() > self.max_length => {
+					// The rest of the line is skipped by the next call.
 					self.is_discarding = true;
-					panic!("Message too long");
+					return Err(Error::MaxLineLengthExceeded {
+						limit: self.max_length,
+					});
 				}
 				(false, None) => {
 					self.next_index = read_to;
@@ -186,6 +217,14 @@ mod tests {
 		assert_eq!(bytes.as_ref(), b"$KE,INF\r\n");
 	}
 
+	#[test]
+	fn join_mask() {
+		assert_eq!(
+			("$KE", "WR", "ALL", 0b1010u32).join_parts(),
+			"$KE,WR,ALL,10"
+		);
+	}
+
 	#[test]
 	fn decode() {
 		let mut bytes = BytesMut::from(b"#INF,Laurent-2\r\n".as_slice());
@@ -197,4 +236,33 @@ mod tests {
 			panic!("No parts found");
 		}
 	}
+
+	#[test]
+	fn decode_default() {
+		let mut bytes = BytesMut::from(b"#OK\r\n".as_slice());
+		let mut codec = Codec::default();
+
+		assert_eq!(
+			codec.decode(&mut bytes).unwrap(),
+			Some(vec!["#OK".to_owned()])
+		);
+	}
+
+	#[test]
+	fn decode_too_long() {
+		let mut bytes = BytesMut::from(b"#INF,Laurent-2\r\n#OK\r\n".as_slice());
+		let mut codec = Codec {
+			max_length: 8,
+			..Codec::new()
+		};
+
+		assert!(matches!(
+			codec.decode(&mut bytes),
+			Err(Error::MaxLineLengthExceeded { limit: 8 })
+		));
+		assert_eq!(
+			codec.decode(&mut bytes).unwrap(),
+			Some(vec!["#OK".to_owned()])
+		);
+	}
 }
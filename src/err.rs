@@ -10,27 +10,95 @@ pub enum Error {
 	#[error(transparent)]
 	ParseInt(#[from] std::num::ParseIntError),
 
+	#[error(transparent)]
+	ParseFloat(#[from] std::num::ParseFloatError),
+
 	#[error(transparent)]
 	Recv(#[from] tokio::sync::broadcast::error::RecvError),
 
+	#[error("Fell behind and missed {0} events")]
+	EventsLagged(u64),
+
 	#[error("Failed to send command")]
 	Send,
 
 	#[error("Channel closed")]
 	Closed,
 
-	#[error("Message syntax error")]
-	SyntaxError,
+	#[error("Timed out waiting for a reply")]
+	Timeout,
+
+	#[error("Message syntax error: {0:?}")]
+	SyntaxError(Vec<String>),
+
+	#[error("A line from the device exceeded {limit} bytes")]
+	MaxLineLengthExceeded { limit: usize },
 
-	#[error("Unknown message")]
-	UnknownMessage,
+	#[error("The device rejected the command: {0}")]
+	Rejected(Rejection),
 
-	#[error("Unexpected message")]
-	UnexpectedMessage,
+	#[error("Unknown message: {0:?}")]
+	UnknownMessage(Vec<String>),
+
+	#[error("Unrecognized reply: {0:?}")]
+	Unrecognized(Vec<String>),
+
+	#[error("Expected a {expected} reply, got {got}")]
+	UnexpectedHeader { expected: &'static str, got: String },
+
+	#[error("Unexpected message: {0:?}")]
+	UnexpectedMessage(Vec<String>),
 
 	#[error("Invalid payload: {0}")]
 	InvalidPayload(String),
 
 	#[error("Authorization failed")]
 	Auth,
+
+	#[error("Not supported by the device")]
+	Unsupported,
+
+	#[error("Sensor fault")]
+	SensorFault,
+
+	#[error("Provisioning failed at {step}: {source}")]
+	Provision { step: String, source: Box<Error> },
+}
+
+/// Why the device rejected a command, for firmware that follows `#ERR` with a code.
+///
+/// The documented protocol only has a bare `#ERR`; the codes mapped here are assumed.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum Rejection {
+	#[error("index out of range")]
+	InvalidIndex,
+
+	#[error("invalid argument")]
+	InvalidArgument,
+
+	#[error("not authorized")]
+	NotAuthorized,
+
+	#[error("device busy")]
+	Busy,
+
+	/// A code the command isn't known to answer with.
+	#[error("code {0}")]
+	Other(String),
+}
+
+impl Error {
+	/// Wraps the error into [`Error::Provision`], recording the step that failed.
+	pub(crate) fn at_step(self, step: impl Into<String>) -> Self {
+		Self::Provision {
+			step: step.into(),
+			source: Box::new(self),
+		}
+	}
+
+	/// Whether the command may succeed if sent again: the reply was lost rather than
+	/// refused by the device.
+	pub fn is_transient(&self) -> bool {
+		matches!(self, Self::Timeout | Self::Io(_))
+	}
 }
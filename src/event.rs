@@ -1,12 +1,23 @@
 use crate::as_match;
+use crate::lio::AdvancedData;
 use crate::lio::Signal;
 use crate::Error;
 use crate::Result;
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::Debug;
 use std::fmt::Display;
 use std::fmt::Formatter;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::broadcast::Receiver;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+	feature = "serde",
+	derive(serde::Serialize, serde::Deserialize),
+	serde(rename_all = "UPPERCASE")
+)]
 pub enum EventKind {
 	Ein,
 	Time,
@@ -15,6 +26,7 @@ pub enum EventKind {
 	Out,
 	Advc,
 	Pwm,
+	#[cfg_attr(feature = "serde", serde(rename = "1WT"))]
 	Wt1,
 }
 
@@ -33,21 +45,131 @@ impl Display for EventKind {
 	}
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Event {
-	Ein { line: u32, signal: Signal },
+	Ein {
+		line: u32,
+		signal: Signal,
+	},
+	/// An `EIN` with modifiers after the level, e.g. `LATCH`. Kept apart from
+	/// [`Ein`](Self::Ein) so that special edges aren't taken for plain ones.
+	EinModified {
+		line: u32,
+		signal: Signal,
+		modifiers: Vec<String>,
+	},
 	Time(u32),
+	/// The level of an input line, reported with the `IN` event category.
+	In {
+		line: u32,
+		signal: Signal,
+	},
+	/// A relay switched, whether by a command or e.g. a button on the module.
+	Rele {
+		relay: u32,
+		state: bool,
+	},
+	Advc {
+		line: u32,
+		data: AdvancedData,
+	},
+	/// The pulse counter of an input line wrapped around to zero.
+	CounterOverflow {
+		line: u32,
+	},
+	/// A 1-Wire temperature reading.
+	Wt1 {
+		sensor: u32,
+		celsius: f32,
+	},
+	Out {
+		channel: u32,
+		signal: Signal,
+	},
+	/// A PWM output changed its duty cycle. The duty is in percent, from 0 to
+	/// [`PWM_DUTY_MAX`](crate::PWM_DUTY_MAX), the same scale
+	/// [`Gateway::set_pwm`](crate::Gateway::set_pwm) takes.
+	Pwm {
+		channel: u32,
+		duty: u16,
+	},
+	/// A sample pushed while device-side ADC sampling is on, see
+	/// [`StreamGateway::adc_stream`](crate::StreamGateway::adc_stream), or an analog
+	/// change reported as `ADVC,<channel>,<value>`. The four-field `ADVC` frames are
+	/// [`Advc`](Self::Advc).
+	Adc {
+		channel: u32,
+		value: u16,
+	},
+	/// An event the crate doesn't model, as the fields following `#M`.
+	Unknown(Vec<String>),
+	/// Periodic sign of life, see
+	/// [`StreamGateway::last_heartbeat`](crate::StreamGateway::last_heartbeat).
+	Heartbeat,
+	/// An event of a kind the crate doesn't know, parsed by a parser registered with
+	/// [`StreamGateway::register_event_parser`](crate::StreamGateway::register_event_parser).
+	Custom(CustomEvent),
 }
 
 impl Event {
+	/// Parses an event in the given format version, as reported by
+	/// [`Gateway::event_format_version`](crate::Gateway::event_format_version).
+	///
+	/// Version 1 is the plain `<kind>,<fields...>` layout understood by
+	/// [`try_from_parts`](Self::try_from_parts). Version 2 prefixes it with the module
+	/// uptime in seconds, which is skipped.
+	pub fn try_from_parts_versioned(msg: &[String], version: u32) -> Result<Self> {
+		Self::try_from_parts(unversioned(msg, version)?)
+	}
+
 	pub fn try_from_parts(msg: &[String]) -> Result<Self> {
 		let event = match as_match!(msg) {
 			["EIN", line, signal] => Self::Ein {
 				line: line.parse()?,
 				signal: signal.parse()?,
 			},
+			["EIN", line, signal, ref modifiers @ ..] => Self::EinModified {
+				line: line.parse()?,
+				signal: signal.parse()?,
+				modifiers: modifiers
+					.iter()
+					.map(|modifier| modifier.to_string())
+					.collect(),
+			},
 			["TIME", time] => Self::Time(time.parse()?),
-			_ => Err(Error::UnknownMessage)?,
+			["RELE", relay, state] => Self::Rele {
+				relay: relay.parse()?,
+				state: state == "1",
+			},
+			["IN", line, signal] => Self::In {
+				line: line.parse()?,
+				signal: signal.parse()?,
+			},
+			["OUT", channel, signal] => Self::Out {
+				channel: channel.parse()?,
+				signal: signal.parse()?,
+			},
+			["PWM", channel, duty] => Self::Pwm {
+				channel: channel.parse()?,
+				duty: duty.parse()?,
+			},
+			["ADVC", line, bits, raw] => Self::Advc {
+				line: line.parse()?,
+				data: AdvancedData::parse(bits, raw)?,
+			},
+			["CNT", line, "OVF"] => Self::CounterOverflow {
+				line: line.parse()?,
+			},
+			["1WT", sensor, celsius] => Self::Wt1 {
+				sensor: sensor.parse()?,
+				celsius: celsius.parse()?,
+			},
+			["ADC" | "ADVC", channel, value] => Self::Adc {
+				channel: channel.parse()?,
+				value: value.parse()?,
+			},
+			["HB"] => Self::Heartbeat,
+			_ => Self::Unknown(msg.to_vec()),
 		};
 
 		Ok(event)
@@ -62,4 +184,315 @@ impl TryFrom<&[String]> for Event {
 	}
 }
 
+/// Strips what the given event format version puts in front of `<kind>,<fields...>`.
+fn unversioned(msg: &[String], version: u32) -> Result<&[String]> {
+	match (version, msg) {
+		(1, msg) => Ok(msg),
+		(2, [_uptime, msg @ ..]) => Ok(msg),
+		(2, []) => Err(Error::UnknownMessage(msg.to_vec())),
+		_ => Err(Error::InvalidPayload(format!(
+			"Unsupported event format version: `{version}`"
+		))),
+	}
+}
+
 pub type EventReceiver = Receiver<Event>;
+
+/// Receives the next event, reporting a subscriber that fell behind as
+/// [`Error::EventsLagged`] with the number of events it missed, and a channel whose gateway
+/// is gone as [`Error::Closed`]. The next call picks up with the oldest event still queued.
+pub async fn recv_event(sub: &mut EventReceiver) -> Result<Event> {
+	sub.recv().await.map_err(|err| match err {
+		RecvError::Lagged(missed) => Error::EventsLagged(missed),
+		RecvError::Closed => Error::Closed,
+	})
+}
+
+/// An event produced by a user-registered parser, see [`Event::Custom`].
+///
+/// Two custom events are equal if they were parsed from the same fields.
+#[derive(Debug, Clone)]
+pub struct CustomEvent {
+	kind: String,
+	fields: Vec<String>,
+	value: Arc<dyn Any + Send + Sync>,
+}
+
+impl CustomEvent {
+	/// The kind token following `#M`.
+	pub fn kind(&self) -> &str {
+		&self.kind
+	}
+
+	/// The fields after the kind, as handed to the parser.
+	pub fn fields(&self) -> &[String] {
+		&self.fields
+	}
+
+	/// The parser's result, if it is a `T`.
+	pub fn downcast_ref<T>(&self) -> Option<&T>
+	where
+		T: Any,
+	{
+		self.value.downcast_ref()
+	}
+}
+
+impl PartialEq for CustomEvent {
+	fn eq(&self, other: &Self) -> bool {
+		self.kind == other.kind && self.fields == other.fields
+	}
+}
+
+impl Eq for CustomEvent {}
+
+type ParseFn = Arc<dyn Fn(&[String]) -> Result<Arc<dyn Any + Send + Sync>> + Send + Sync>;
+
+/// Parsers for event kinds the crate doesn't know, keyed by kind token.
+#[derive(Clone, Default)]
+pub(crate) struct EventParsers(HashMap<String, ParseFn>);
+
+impl EventParsers {
+	pub(crate) fn register<T, F>(&mut self, kind: &str, parse: F)
+	where
+		T: Any + Send + Sync,
+		F: Fn(&[String]) -> Result<T> + Send + Sync + 'static,
+	{
+		let parse: ParseFn = Arc::new(move |fields| Ok(Arc::new(parse(fields)?)));
+		self.0.insert(kind.to_owned(), parse);
+	}
+
+	/// Parses an event like [`Event::try_from_parts_versioned`], falling back to the
+	/// registered parsers for kinds it doesn't recognise.
+	pub(crate) fn parse(&self, msg: &[String], version: u32) -> Result<Event> {
+		let msg = unversioned(msg, version)?;
+		let event = Event::try_from_parts(msg)?;
+		let (Event::Unknown(_), [kind, fields @ ..]) = (&event, msg) else {
+			return Ok(event);
+		};
+		let Some(parse) = self.0.get(kind) else {
+			return Ok(event);
+		};
+
+		Ok(Event::Custom(CustomEvent {
+			kind: kind.clone(),
+			fields: fields.to_vec(),
+			value: parse(fields)?,
+		}))
+	}
+}
+
+impl Debug for EventParsers {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		f.debug_set().entries(self.0.keys()).finish()
+	}
+}
+
+/// An event together with the fields it was parsed from, `#M` included, as delivered by
+/// [`StreamGateway::subscribe_with_parts`](crate::StreamGateway::subscribe_with_parts).
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawEvent {
+	pub event: Event,
+	pub parts: Vec<String>,
+}
+
+/// A digital input level, whether it was polled with
+/// [`Gateway::line_signal`](crate::Gateway::line_signal) or pushed as [`Event::Ein`] or
+/// [`Event::In`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineChange {
+	pub line: u32,
+	pub signal: Signal,
+}
+
+impl LineChange {
+	pub fn new(line: u32, signal: Signal) -> Self {
+		Self { line, signal }
+	}
+}
+
+impl From<Event> for Option<LineChange> {
+	fn from(value: Event) -> Self {
+		match value {
+			Event::Ein { line, signal } | Event::In { line, signal } => {
+				Some(LineChange::new(line, signal))
+			}
+			_ => None,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn line_change_from_event() {
+		let change: Option<LineChange> = Event::Ein {
+			line: 2,
+			signal: Signal::High,
+		}
+		.into();
+		assert_eq!(change, Some(LineChange::new(2, Signal::High)));
+
+		let change: Option<LineChange> = Event::Time(42).into();
+		assert_eq!(change, None);
+	}
+
+	#[test]
+	fn in_out_events() {
+		let parts = ["IN", "3", "1"].map(String::from);
+		let event = Event::try_from_parts(&parts).unwrap();
+		assert_eq!(
+			event,
+			Event::In {
+				line: 3,
+				signal: Signal::High,
+			}
+		);
+		let change: Option<LineChange> = event.into();
+		assert_eq!(change, Some(LineChange::new(3, Signal::High)));
+
+		let parts = ["OUT", "1", "0"].map(String::from);
+		assert_eq!(
+			Event::try_from_parts(&parts).unwrap(),
+			Event::Out {
+				channel: 1,
+				signal: Signal::Low,
+			}
+		);
+	}
+
+	#[test]
+	fn advc_event() {
+		let parts = ["ADVC", "2", "26", "2242469"].map(String::from);
+		assert_eq!(
+			Event::try_from_parts(&parts).unwrap(),
+			Event::Advc {
+				line: 2,
+				data: AdvancedData {
+					bits: 26,
+					raw: 0x2242469,
+				},
+			}
+		);
+	}
+
+	#[test]
+	fn advc_analog_event() {
+		let parts = ["ADVC", "0", "1500"].map(String::from);
+		assert_eq!(
+			Event::try_from_parts(&parts).unwrap(),
+			Event::Adc {
+				channel: 0,
+				value: 1500,
+			}
+		);
+	}
+
+	#[test]
+	fn ein_modifiers() {
+		let parts = ["EIN", "1", "1"].map(String::from);
+		assert_eq!(
+			Event::try_from_parts(&parts).unwrap(),
+			Event::Ein {
+				line: 1,
+				signal: Signal::High,
+			}
+		);
+
+		let parts = ["EIN", "1", "1", "LATCH"].map(String::from);
+		assert_eq!(
+			Event::try_from_parts(&parts).unwrap(),
+			Event::EinModified {
+				line: 1,
+				signal: Signal::High,
+				modifiers: vec!["LATCH".into()],
+			}
+		);
+	}
+
+	#[test]
+	fn wt1_event() {
+		let parts = ["1WT", "0", "-5.2"].map(String::from);
+		assert_eq!(
+			Event::try_from_parts(&parts).unwrap(),
+			Event::Wt1 {
+				sensor: 0,
+				celsius: -5.2,
+			}
+		);
+	}
+
+	#[test]
+	fn pwm_event() {
+		let parts = ["PWM", "1", "75"].map(String::from);
+		assert_eq!(
+			Event::try_from_parts(&parts).unwrap(),
+			Event::Pwm {
+				channel: 1,
+				duty: 75,
+			}
+		);
+	}
+
+	#[test]
+	fn counter_overflow_event() {
+		let parts = ["CNT", "3", "OVF"].map(String::from);
+		assert_eq!(
+			Event::try_from_parts(&parts).unwrap(),
+			Event::CounterOverflow { line: 3 }
+		);
+	}
+
+	#[test]
+	fn unknown_event() {
+		let parts = ["FOO", "1", "2", "3"].map(String::from);
+		assert_eq!(
+			Event::try_from_parts(&parts).unwrap(),
+			Event::Unknown(parts.to_vec())
+		);
+	}
+
+	#[test]
+	fn rele_event() {
+		let parts = ["RELE", "2", "1"].map(String::from);
+		assert_eq!(
+			Event::try_from_parts(&parts).unwrap(),
+			Event::Rele {
+				relay: 2,
+				state: true,
+			}
+		);
+	}
+
+	#[test]
+	fn out_event() {
+		let parts = ["OUT", "5", "1"].map(String::from);
+		assert_eq!(
+			Event::try_from_parts(&parts).unwrap(),
+			Event::Out {
+				channel: 5,
+				signal: Signal::High,
+			}
+		);
+	}
+
+	#[test]
+	fn versioned_event() {
+		let ein = Event::Ein {
+			line: 1,
+			signal: Signal::High,
+		};
+
+		let v1 = ["EIN", "1", "1"].map(String::from);
+		assert_eq!(Event::try_from_parts_versioned(&v1, 1).unwrap(), ein);
+
+		let v2 = ["86400", "EIN", "1", "1"].map(String::from);
+		assert_eq!(Event::try_from_parts_versioned(&v2, 2).unwrap(), ein);
+		assert_eq!(
+			Event::try_from_parts_versioned(&v2, 1).unwrap(),
+			Event::Unknown(v2.to_vec())
+		);
+	}
+}
@@ -0,0 +1,355 @@
+use crate::as_match;
+use crate::codec::Codec;
+use crate::codec::JoinParts;
+use crate::lio::check_input;
+use crate::lio::check_relay;
+use crate::ClickDelay;
+use crate::EventKind;
+use crate::Result;
+use crate::Signal;
+use crate::StreamGateway;
+use crate::INPUT_COUNT;
+use crate::RELAY_COUNT;
+use futures::SinkExt;
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::Mutex;
+use tokio::io::DuplexStream;
+use tokio::sync::mpsc;
+use tokio_util::codec::Framed;
+
+/// An in-memory Laurent-2 for integration tests.
+///
+/// The device keeps relay, input, ADC and clock state and answers the command set the way a
+/// board does. Inputs and the clock are driven from the test; their changes, and those of
+/// the relays, are pushed as events once enabled with `$KE,MSG,S,<kind>,SET,ON`. A device
+/// with a password answers `#ERR` to everything but `$KE` and `$KE,PSW,SET` until it has
+/// been authorized.
+#[derive(Debug, Clone, Default)]
+pub struct FakeDevice {
+	state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug)]
+struct State {
+	password: Option<String>,
+	authorized: bool,
+	relays: Vec<bool>,
+	inputs: Vec<Signal>,
+	adc: HashMap<u32, u16>,
+	time: u32,
+	events: HashSet<String>,
+	/// Unsolicited lines for the current connection.
+	outgoing: Option<mpsc::UnboundedSender<String>>,
+}
+
+impl Default for State {
+	fn default() -> Self {
+		Self {
+			password: None,
+			authorized: false,
+			relays: vec![false; RELAY_COUNT as usize],
+			inputs: vec![Signal::Low; INPUT_COUNT as usize],
+			adc: HashMap::new(),
+			time: 0,
+			events: HashSet::new(),
+			outgoing: None,
+		}
+	}
+}
+
+impl State {
+	fn emit(&self, kind: EventKind, fields: String) {
+		if let Some(outgoing) = &self.outgoing {
+			if self.events.contains(&kind.to_string()) {
+				let _ = outgoing.send(format!("#M,{kind},{fields}"));
+			}
+		}
+	}
+
+	/// Switches a relay, emitting `RELE` if its state changes.
+	fn set_relay(&mut self, index: usize, on: bool) {
+		if self.relays[index] != on {
+			self.relays[index] = on;
+			self.emit(EventKind::Rele, format!("{},{}", index + 1, u8::from(on)));
+		}
+	}
+}
+
+impl FakeDevice {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Requires `$KE,PSW,SET,<password>` before other commands are accepted.
+	pub fn with_password(self, password: impl Into<String>) -> Self {
+		self.state.lock().unwrap().password = Some(password.into());
+		self
+	}
+
+	/// Connects a [`StreamGateway`] to the device. A new connection replaces the previous
+	/// one as the receiver of events and starts out unauthorized with all events off.
+	pub fn connect(&self) -> StreamGateway {
+		StreamGateway::connect(self.serve())
+	}
+
+	/// Starts serving a connection and returns its client end.
+	pub fn serve(&self) -> DuplexStream {
+		let (client, device) = tokio::io::duplex(1024);
+		let (tx, mut outgoing) = mpsc::unbounded_channel();
+		{
+			let mut state = self.state.lock().unwrap();
+			state.outgoing = Some(tx);
+			state.authorized = false;
+			state.events.clear();
+		}
+
+		let this = self.clone();
+		tokio::spawn(async move {
+			let mut stream = Framed::new(device, Codec::new());
+
+			loop {
+				let line = tokio::select! {
+					msg = stream.next() => match msg {
+						Some(Ok(parts)) => this.handle(&parts),
+						Some(Err(_)) => "#ERR".to_owned(),
+						None => break,
+					},
+					line = outgoing.recv() => match line {
+						Some(line) => line,
+						None => break,
+					},
+				};
+
+				if stream.send(line.boxed()).await.is_err() {
+					break;
+				}
+			}
+		});
+
+		client
+	}
+
+	/// Drops the current connection, like a module that lost its network link. Changes made
+	/// until the next [`serve`](Self::serve) aren't pushed to anyone.
+	pub fn disconnect(&self) {
+		self.state.lock().unwrap().outgoing = None;
+	}
+
+	pub fn relay(&self, relay: u32) -> Result<bool> {
+		check_relay(relay)?;
+		Ok(self.state.lock().unwrap().relays[relay as usize - 1])
+	}
+
+	pub fn input(&self, line: u32) -> Result<Signal> {
+		check_input(line)?;
+		Ok(self.state.lock().unwrap().inputs[line as usize - 1])
+	}
+
+	/// Changes the level of an input, emitting `EIN` if it differs from the current one.
+	pub fn set_input(&self, line: u32, signal: Signal) -> Result<()> {
+		check_input(line)?;
+		let mut state = self.state.lock().unwrap();
+		let current = &mut state.inputs[line as usize - 1];
+		if *current != signal {
+			*current = signal;
+			state.emit(EventKind::Ein, format!("{line},{signal}"));
+		}
+		Ok(())
+	}
+
+	pub fn set_adc(&self, channel: u32, value: u16) {
+		self.state.lock().unwrap().adc.insert(channel, value);
+	}
+
+	/// Moves the module clock forward, emitting `TIME`.
+	pub fn advance_clock(&self, secs: u32) {
+		let mut state = self.state.lock().unwrap();
+		state.time += secs;
+		let time = state.time;
+		state.emit(EventKind::Time, time.to_string());
+	}
+
+	fn handle(&self, parts: &[String]) -> String {
+		let mut state = self.state.lock().unwrap();
+		let locked = state.password.is_some() && !state.authorized;
+
+		match as_match!(parts) {
+			["$KE"] => "#OK".into(),
+			["$KE", "PSW", "SET", pwd] => match state.password.as_deref() {
+				Some(password) if password != pwd => "#PSW,SET,ERR".into(),
+				_ => {
+					state.authorized = true;
+					"#PSW,SET,OK".into()
+				}
+			},
+			_ if locked => "#ERR".into(),
+			["$KE", "MSG", "S", kind, "SET", enabled @ ("ON" | "OFF")] => {
+				if enabled == "ON" {
+					state.events.insert(kind.to_string());
+				} else {
+					state.events.remove(kind);
+				}
+				"#MSG,SET,OK".into()
+			}
+			["$KE", "REL", relay, action, ref delay @ ..] => {
+				let Some(index) = parse_relay(relay) else {
+					return "#ERR".into();
+				};
+				let delay = match delay {
+					[] => None,
+					[delay] => match ClickDelay::parse(delay) {
+						Ok(delay) => Some(delay.duration()),
+						Err(_) => return "#ERR".into(),
+					},
+					_ => return "#ERR".into(),
+				};
+
+				let on = match action {
+					"1" => true,
+					"0" => false,
+					"2" => !state.relays[index],
+					_ => return "#ERR".into(),
+				};
+				state.set_relay(index, on);
+
+				if let Some(delay) = delay.filter(|_| on) {
+					let this = self.clone();
+					tokio::spawn(async move {
+						tokio::time::sleep(delay).await;
+						this.state.lock().unwrap().set_relay(index, false);
+					});
+				}
+				"#REL,OK".into()
+			}
+			["$KE", "RDR", relay] => match parse_relay(relay) {
+				Some(index) => format!("#RDR,{relay},{}", u8::from(state.relays[index])),
+				None => "#ERR".into(),
+			},
+			["$KE", "RD", line] => match line.parse().ok().filter(|line| check_input(*line).is_ok()) {
+				Some(line) => format!("#RD,{line},{}", state.inputs[line as usize - 1]),
+				None => "#ERR".into(),
+			},
+			["$KE", "ADC", channel] => match channel.parse::<u32>() {
+				Ok(channel) => format!("#ADC,{channel},{}", state.adc.get(&channel).unwrap_or(&0)),
+				Err(_) => "#ERR".into(),
+			},
+			_ => "#ERR".into(),
+		}
+	}
+}
+
+fn parse_relay(relay: &str) -> Option<usize> {
+	let relay = relay
+		.parse()
+		.ok()
+		.filter(|relay| check_relay(*relay).is_ok())?;
+	Some(relay as usize - 1)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::Error;
+	use crate::Event;
+	use crate::Gateway;
+	use crate::RelayAction;
+	use std::time::Duration;
+
+	#[tokio::test]
+	async fn fake_device_commands() -> Result<()> {
+		let device = FakeDevice::new().with_password("secret");
+		let gw = device.connect();
+
+		gw.ping().await?;
+		assert!(matches!(
+			gw.relay_status(1).await,
+			Err(Error::SyntaxError(_))
+		));
+		assert!(matches!(gw.authorize("wrong").await, Err(Error::Auth)));
+		gw.authorize("secret").await?;
+
+		gw.relay(2, RelayAction::On, None).await?;
+		assert!(gw.relay_status(2).await?);
+		assert!(device.relay(2)?);
+		gw.relay(2, RelayAction::Toggle, None).await?;
+		assert!(!gw.relay_status(2).await?);
+
+		device.set_input(3, Signal::High)?;
+		assert_eq!(gw.line_signal(3).await?, Signal::High);
+		assert!(matches!(device.relay(0), Err(Error::InvalidPayload(_))));
+		assert!(matches!(
+			device.set_input(7, Signal::High),
+			Err(Error::InvalidPayload(_))
+		));
+		assert!(matches!(
+			gw.line_signal(9).await,
+			Err(Error::SyntaxError(_))
+		));
+
+		device.set_adc(1, 512);
+		let reply = gw.raw_command(vec!["ADC".into(), "1".into()]).await?;
+		assert_eq!(reply, ["#ADC", "1", "512"]);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn fake_device_click() -> Result<()> {
+		let device = FakeDevice::new();
+		let gw = device.connect();
+
+		gw.relay(1, RelayAction::On, Some(ClickDelay::Millis100(1)))
+			.await?;
+		assert!(gw.relay_status(1).await?);
+		tokio::time::sleep(Duration::from_millis(200)).await;
+		assert!(!gw.relay_status(1).await?);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn fake_device_events() -> Result<()> {
+		let device = FakeDevice::new();
+		let gw = device.connect();
+		let mut sub = gw.subscibe();
+
+		// Nothing is pushed until the event is enabled.
+		device.set_input(1, Signal::High)?;
+		gw.cfg_event(EventKind::Ein, true).await?;
+		device.set_input(1, Signal::High)?;
+		device.set_input(2, Signal::High)?;
+
+		assert_eq!(
+			sub.recv().await.unwrap(),
+			Event::Ein {
+				line: 2,
+				signal: Signal::High,
+			}
+		);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn fake_device_relay_events() -> Result<()> {
+		let device = FakeDevice::new();
+		let gw = device.connect();
+		let mut sub = gw.subscibe();
+		gw.cfg_event(EventKind::Rele, true).await?;
+
+		gw.relay(3, RelayAction::On, None).await?;
+		// No change, no event.
+		gw.relay(3, RelayAction::On, None).await?;
+		gw.relay(3, RelayAction::Toggle, None).await?;
+
+		for state in [true, false] {
+			assert_eq!(sub.recv().await.unwrap(), Event::Rele { relay: 3, state });
+		}
+		assert!(sub.try_recv().is_err());
+
+		Ok(())
+	}
+}
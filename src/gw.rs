@@ -1,341 +1,4627 @@
-use crate::{as_match, Signal};
+use crate::as_match;
 use crate::codec::Codec;
 use crate::codec::JoinParts;
+use crate::event::recv_event;
 use crate::event::Event;
+use crate::event::EventParsers;
+use crate::lio::check_input;
+use crate::lio::check_output;
+use crate::lio::check_pulse;
+use crate::lio::check_pwm;
+use crate::lio::check_relay;
 use crate::utils::is_event;
+use crate::AdvancedData;
+use crate::Audit;
+use crate::BoardConfig;
+use crate::BoardSnapshot;
+use crate::Capabilities;
 use crate::ClickDelay;
+use crate::Command;
+use crate::CommandRecord;
 use crate::Error;
 use crate::EventKind;
+use crate::FirmwareInfo;
+use crate::GatewayOptions;
+use crate::OneWireSensor;
+use crate::RawEvent;
+use crate::Rejection;
 use crate::RelayAction;
+use crate::RelayLink;
 use crate::Result;
+use crate::Signal;
+use crate::ADC_COUNT;
+use crate::PWM_DUTY_MAX;
+use crate::RELAY_COUNT;
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use futures::stream::FuturesUnordered;
+use futures::FutureExt;
 use futures::SinkExt;
+use futures::Stream;
 use futures::StreamExt;
+use socket2::SockRef;
+use socket2::TcpKeepalive;
+use std::any::Any;
+use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::fmt::Debug;
-use async_trait::async_trait;
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::Weak;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
 use tokio::io::AsyncRead;
 use tokio::io::AsyncWrite;
+use tokio::net::TcpStream;
+use tokio::net::ToSocketAddrs;
 use tokio::sync::broadcast;
 use tokio::sync::broadcast::Receiver as BroadcastReceiver;
-use tokio::sync::broadcast::Sender as Broadcaster;
 use tokio::sync::mpsc;
-use tokio::sync::mpsc::Receiver;
+use tokio::sync::mpsc::error::SendError;
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::sync::mpsc::Sender;
-use tokio::sync::Mutex;
+use tokio::sync::oneshot;
+use tokio::sync::watch;
+use tokio_util::bytes::Bytes;
 use tokio_util::codec::Framed;
+use tokio_util::sync::CancellationToken;
 
 #[async_trait]
 pub trait Gateway: Debug {
 	fn subscibe(&self) -> BroadcastReceiver<Event>;
+
+	/// Follows the connection to the module, for gateways that reconnect without closing
+	/// their event channel. `None` if the gateway has no such state.
+	fn watch_connection(&self) -> Option<watch::Receiver<ConnectionState>> {
+		None
+	}
+
 	async fn ping(&self) -> Result<()>;
+
+	/// Like [`ping`](Self::ping), returning how long the module took to answer.
+	async fn ping_rtt(&self) -> Result<Duration> {
+		let start = Instant::now();
+		self.ping().await?;
+		Ok(start.elapsed())
+	}
+
+	/// Logs in with `pwd`. Modules with a password answer `#ERR` to most commands until then.
+	///
+	/// This doesn't change the password; see [`change_password`](Self::change_password).
 	async fn authorize(&self, pwd: &str) -> Result<()>;
+
+	/// Replaces the password the module asks for. Fails with [`Error::Auth`] if the module
+	/// refuses, e.g. because the gateway hasn't logged in.
+	async fn change_password(&self, _new: &str) -> Result<()> {
+		Err(Error::Unsupported)
+	}
+
 	async fn cfg_event(&self, kind: EventKind, enabled: bool) -> Result<()>;
 	async fn relay(&self, relay: u32, action: RelayAction, delay: Option<ClickDelay>) -> Result<()>;
 	async fn relay_status(&self, relay: u32) -> Result<bool>;
 	async fn line_signal(&self, line: u32) -> Result<Signal>;
+
+	/// Frequency in Hz measured on an input in frequency mode, e.g. a tachometer.
+	///
+	/// A line in any other mode has no measurement, which is reported as
+	/// [`Error::InvalidPayload`].
+	async fn input_frequency(&self, _line: u32) -> Result<f32> {
+		Err(Error::Unsupported)
+	}
+
+	/// Pulses counted on an input since the counter was last reset.
+	async fn read_counter(&self, _line: u32) -> Result<u32> {
+		Err(Error::Unsupported)
+	}
+
+	/// Sets the pulse counter of an input back to zero.
+	async fn reset_counter(&self, _line: u32) -> Result<()> {
+		Err(Error::Unsupported)
+	}
+
+	/// Reads the raw value of an ADC channel.
+	async fn read_adc(&self, _channel: u32) -> Result<u16> {
+		Err(Error::Unsupported)
+	}
+
+	/// Reads the temperature of the module's own MCU in °C.
+	///
+	/// This is not a 1-Wire probe; it tracks the enclosure rather than the environment.
+	async fn board_temperature(&self) -> Result<f32> {
+		Err(Error::Unsupported)
+	}
+
+	/// Time since the relay last changed state, for firmware that keeps track of it.
+	///
+	/// See [`Board::relay_last_change`](crate::Board::relay_last_change) for a fallback that
+	/// works with any firmware.
+	async fn relay_last_change(&self, _relay: u32) -> Result<Duration> {
+		Err(Error::Unsupported)
+	}
+
+	/// Time left on each relay's device-side click timer, `None` for relays without one,
+	/// read in a single snapshot.
+	async fn relay_timers(&self) -> Result<Vec<Option<Duration>>> {
+		Err(Error::Unsupported)
+	}
+
+	/// Number of times the relay has switched, for firmware that counts actuations.
+	///
+	/// See [`Board::relay_cycle_count`](crate::Board::relay_cycle_count) for a fallback that
+	/// works with any firmware.
+	async fn relay_cycle_count(&self, _relay: u32) -> Result<u64> {
+		Err(Error::Unsupported)
+	}
+
+	/// Reads two ADC channels wired as a differential pair and returns `pos - neg`.
+	async fn adc_differential(&self, _pos: u32, _neg: u32) -> Result<i32> {
+		Err(Error::Unsupported)
+	}
+
+	/// Reads every ADC channel in one command, so the values are taken at the same moment.
+	/// Channel 1 comes first.
+	async fn adc_all(&self) -> Result<Vec<u16>> {
+		Err(Error::Unsupported)
+	}
+
+	/// Reads every relay's state in one command, relay 1 first.
+	async fn read_all_relays(&self) -> Result<Vec<bool>> {
+		Err(Error::Unsupported)
+	}
+
+	/// Reads every input line in one command, line 1 first.
+	async fn read_all_inputs(&self) -> Result<Vec<Signal>> {
+		Err(Error::Unsupported)
+	}
+
+	/// Reads relays, inputs and ADC channels in a single frame, for firmware that has one.
+	///
+	/// See [`Board::snapshot`](crate::Board::snapshot) for a fallback that works with any
+	/// firmware.
+	async fn status_snapshot(&self) -> Result<BoardSnapshot> {
+		Err(Error::Unsupported)
+	}
+
+	/// Drives a logic-level (transistor) output, as opposed to a relay.
+	async fn set_output(&self, _channel: u32, _level: Signal) -> Result<()> {
+		Err(Error::Unsupported)
+	}
+
+	/// Reads back the level of a logic-level output.
+	async fn output(&self, _channel: u32) -> Result<Signal> {
+		Err(Error::Unsupported)
+	}
+
+	/// Emits a burst of `count` pulses at `freq_hz` on a logic-level output, timed by the
+	/// module rather than the host, e.g. to step a motor driver.
+	async fn pulse_output(&self, _channel: u32, _count: u32, _freq_hz: u32) -> Result<()> {
+		Err(Error::Unsupported)
+	}
+
+	/// Makes the module drive `relay` from input `line` by itself, which keeps simple
+	/// interlocks working while the host is offline.
+	async fn link_relay_to_input(&self, _relay: u32, _line: u32, _invert: bool) -> Result<()> {
+		Err(Error::Unsupported)
+	}
+
+	/// Returns the relay to host control.
+	async fn unlink_relay(&self, _relay: u32) -> Result<()> {
+		Err(Error::Unsupported)
+	}
+
+	/// The input `relay` currently follows, if any.
+	async fn relay_link(&self, _relay: u32) -> Result<Option<RelayLink>> {
+		Err(Error::Unsupported)
+	}
+
+	/// Model, firmware version, build date and checksum of the module, as far as the
+	/// firmware reports them.
+	async fn firmware_details(&self) -> Result<FirmwareInfo> {
+		Err(Error::Unsupported)
+	}
+
+	/// Model name of the module, e.g. `Laurent-2`, to confirm the right device was reached.
+	async fn module_info(&self) -> Result<String> {
+		self.firmware_details().await.map(|info| info.model)
+	}
+
+	/// Layout version of the `#M` event frames the module emits.
+	///
+	/// Events are parsed as version 1 until this has been queried.
+	async fn event_format_version(&self) -> Result<u32> {
+		Err(Error::Unsupported)
+	}
+
+	/// Reads a 1-Wire temperature sensor in °C.
+	async fn read_temperature(&self, _sensor: u32) -> Result<f32> {
+		Err(Error::Unsupported)
+	}
+
+	/// Lowest and highest temperature in °C a 1-Wire sensor has seen since the last reset,
+	/// for firmware that keeps track of them.
+	///
+	/// See [`TempSensor`](crate::TempSensor) for tracking them on the client instead.
+	async fn temperature_extremes(&self, _sensor: u32) -> Result<(f32, f32)> {
+		Err(Error::Unsupported)
+	}
+
+	async fn reset_temperature_extremes(&self, _sensor: u32) -> Result<()> {
+		Err(Error::Unsupported)
+	}
+
+	/// Commands the firmware understands, as listed by its help command.
+	///
+	/// Sub-commands are listed after their command, separated by a space (e.g. `ADC DIF`).
+	async fn supported_commands(&self) -> Result<Vec<String>> {
+		Err(Error::Unsupported)
+	}
+
+	/// Reads the last frame captured by an input in advanced mode (e.g. a Wiegand reader).
+	async fn advanced_input(&self, _line: u32) -> Result<AdvancedData> {
+		Err(Error::Unsupported)
+	}
+
+	/// Enumerates the sensors present on the 1-Wire bus.
+	async fn scan_onewire(&self) -> Result<Vec<OneWireSensor>> {
+		Err(Error::Unsupported)
+	}
+
+	/// Sets the duty cycle of a PWM output in percent, from 0 to [`PWM_DUTY_MAX`]. The
+	/// module reports changes on the same scale in [`Event::Pwm`].
+	///
+	/// [`PWM_DUTY_MAX`]: crate::PWM_DUTY_MAX
+	async fn set_pwm(&self, _channel: u32, _duty: u8) -> Result<()> {
+		Err(Error::Unsupported)
+	}
+
+	/// Restarts the module, e.g. to recover one that stopped responding properly.
+	///
+	/// The module may drop the connection before answering; that counts as success. A
+	/// gateway from [`StreamGateway::connect_reconnecting`] reconnects by itself, others have
+	/// to be reopened.
+	async fn reboot(&self) -> Result<()> {
+		Err(Error::Unsupported)
+	}
+
+	/// Persists the current configuration so it survives a power cycle.
+	async fn save_settings(&self) -> Result<()> {
+		Err(Error::Unsupported)
+	}
+
+	/// The full configuration of the module as text, one setting per line, for backing it
+	/// up before a firmware update.
+	async fn export_config(&self) -> Result<String> {
+		Err(Error::Unsupported)
+	}
+
+	/// Restores a configuration taken with [`export_config`](Self::export_config).
+	///
+	/// The module may reboot to apply it, closing the connection; that counts as success,
+	/// and the gateway has to be reopened afterwards.
+	async fn import_config(&self, _blob: &str) -> Result<()> {
+		Err(Error::Unsupported)
+	}
+
+	/// Makes the module reset its outputs if no command arrives within `window`, as a
+	/// failsafe for a host that went away. `None` turns the watchdog off.
+	///
+	/// Any command restarts the window. A host that may sit idle for longer has to keep
+	/// sending something, e.g. a [`ping`](Self::ping), at an interval comfortably below
+	/// `window`, or the watchdog fires while the host is still alive. The window has a
+	/// resolution of one second; other windows are rejected with [`Error::InvalidPayload`],
+	/// as is one that [`StreamGateway::spawn_keepalive`] running on the gateway wouldn't
+	/// restart in time.
+	async fn set_command_watchdog(&self, _window: Option<Duration>) -> Result<()> {
+		Err(Error::Unsupported)
+	}
+
+	/// Reads back the configuration [`provision`](Self::provision) applies, as far as the
+	/// module reports it: relay links and relay states. The password and event settings
+	/// can't be queried and are left out.
+	async fn running_config(&self) -> Result<BoardConfig> {
+		read_outputs(self).await
+	}
+
+	/// Applies `cfg`: the password first, then events, relay links and relay states, and
+	/// finally saves the result. Stops at the first failing step and reports which one it
+	/// was.
+	async fn provision(&self, cfg: &BoardConfig) -> Result<()> {
+		if let Some(pwd) = &cfg.password {
+			let res = self.change_password(pwd).await;
+			res.map_err(|err| err.at_step("password"))?;
+		}
+
+		for &(kind, enabled) in &cfg.events {
+			let res = self.cfg_event(kind, enabled).await;
+			res.map_err(|err| err.at_step(format!("event {kind}")))?;
+		}
+
+		for (relay, link) in &cfg.links {
+			let res = match link {
+				Some(link) => {
+					self
+						.link_relay_to_input(*relay, link.line, link.invert)
+						.await
+				}
+				None => self.unlink_relay(*relay).await,
+			};
+			res.map_err(|err| err.at_step(format!("link of relay {relay}")))?;
+		}
+
+		let res = self.reconcile_relays(&cfg.relays).await;
+		res.map_err(|err| err.at_step("relays"))?;
+
+		let res = self.save_settings().await;
+		res.map_err(|err| err.at_step("save"))
+	}
+
+	/// Switches several relays with one command, so they change together.
+	///
+	/// Bit `n - 1` of `mask` selects relay `n`; the same bit of `states` says whether it is
+	/// turned on or off. Relays outside `mask` are left alone.
+	async fn write_relays(&self, _mask: u32, _states: u32) -> Result<()> {
+		Err(Error::Unsupported)
+	}
+
+	/// Sets every relay at once: bit `n - 1` of `mask` turns relay `n` on, a clear bit
+	/// turns it off.
+	async fn write_all_relays(&self, _mask: u32) -> Result<()> {
+		Err(Error::Unsupported)
+	}
+
+	/// Drives relays `1..=desired.len()` to the given states, switching only those that
+	/// differ from what the device reports.
+	///
+	/// The states are read with [`read_all_relays`](Self::read_all_relays) and the changes
+	/// go out as a single [`write_relays`](Self::write_relays). Firmware without either bulk
+	/// command (answering `#ERR` to it) is handled relay by relay instead. More states than
+	/// [`RELAY_COUNT`] are rejected with [`Error::InvalidPayload`].
+	async fn reconcile_relays(&self, desired: &[bool]) -> Result<()> {
+		if desired.len() > RELAY_COUNT as usize {
+			return Err(Error::InvalidPayload(format!(
+				"The module has {RELAY_COUNT} relays. Received {} states",
+				desired.len()
+			)));
+		}
+
+		let current = match self.read_all_relays().await {
+			Err(Error::Unsupported | Error::SyntaxError(_)) => {
+				let mut current = Vec::with_capacity(desired.len());
+				for relay in (1..).take(desired.len()) {
+					current.push(self.relay_status(relay).await?);
+				}
+				current
+			}
+			res => res?,
+		};
+
+		if current.len() < desired.len() {
+			return Err(Error::InvalidPayload(format!(
+				"The module reported {} relays. Received {} states",
+				current.len(),
+				desired.len()
+			)));
+		}
+
+		let (mask, states) = desired
+			.iter()
+			.zip(current)
+			.enumerate()
+			.filter(|&(_, (&on, current))| on != current)
+			.fold((0, 0), |(mask, states), (index, (&on, _))| {
+				let bit = 1 << index;
+				(mask | bit, if on { states | bit } else { states })
+			});
+
+		if mask == 0 {
+			return Ok(());
+		}
+
+		match self.write_relays(mask, states).await {
+			Err(Error::Unsupported | Error::SyntaxError(_)) => (),
+			res => return res,
+		}
+
+		for relay in (1..=RELAY_COUNT).filter(|relay| mask & 1 << (relay - 1) != 0) {
+			let action = if states & 1 << (relay - 1) != 0 {
+				RelayAction::On
+			} else {
+				RelayAction::Off
+			};
+			self.relay(relay, action, None).await?;
+		}
+
+		Ok(())
+	}
+}
+
+/// See [`StreamGateway::rejected`].
+fn rejection(reply: &[String], codes: &[(&str, Rejection)]) -> Error {
+	let Some(code) = reply.get(1) else {
+		return Error::SyntaxError(reply.to_vec());
+	};
+
+	let rejection = codes
+		.iter()
+		.chain(COMMON_CODES)
+		.find(|(known, _)| known == code)
+		.map(|(_, rejection)| rejection.clone())
+		.unwrap_or_else(|| Rejection::Other(reply[1..].join(",")));
+	Error::Rejected(rejection)
+}
+
+/// Reads the links and states of all relays into a [`BoardConfig`].
+async fn read_outputs<G>(gw: &G) -> Result<BoardConfig>
+where
+	G: Gateway + Sync + ?Sized,
+{
+	let mut cfg = BoardConfig::default();
+	for relay in 1..=RELAY_COUNT {
+		cfg.links.push((relay, gw.relay_link(relay).await?));
+		cfg.relays.push(gw.relay_status(relay).await?);
+	}
+
+	Ok(cfg)
+}
+
+/// Renders a masked relay write as one character per relay, relay 1 first: `1` for on,
+/// `0` for off and `x` for unchanged.
+fn relay_pattern(mask: u32, states: u32) -> String {
+	let len = RELAY_COUNT.max(u32::BITS - mask.leading_zeros());
+	(0..len)
+		.map(|bit| match ((mask >> bit) & 1, (states >> bit) & 1) {
+			(0, _) => 'x',
+			(_, 1) => '1',
+			_ => '0',
+		})
+		.collect()
+}
+
+/// Parses the time left on a relay timer: tenths of a second, or `-` if none is running.
+fn parse_timer(timer: &str) -> Result<Option<Duration>> {
+	match timer {
+		"-" => Ok(None),
+		tenths => Ok(Some(Duration::from_millis(tenths.parse::<u64>()? * 100))),
+	}
+}
+
+fn configure_tcp(stream: &TcpStream, opts: &GatewayOptions) -> Result<()> {
+	if opts.nodelay {
+		stream.set_nodelay(true)?;
+	}
+	if let Some(idle) = opts.keepalive {
+		SockRef::from(stream).set_tcp_keepalive(&TcpKeepalive::new().with_time(idle))?;
+	}
+
+	Ok(())
+}
+
+/// How long [`StreamGateway::raw_command_multi`] waits for the next reply frame.
+pub const MULTI_REPLY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long [`StreamGateway::close`] waits for the device to acknowledge the logout.
+pub const LOGOUT_TIMEOUT: Duration = Duration::from_secs(3);
+
+// The Laurent-2 documentation only describes a bare `#ERR`. The codes below are assumed, not
+// taken from it: they are what firmware that explains its rejections is expected to send.
+// Any other code is passed on as `Rejection::Other`.
+
+/// `#ERR` codes any command may answer with.
+const COMMON_CODES: &[(&str, Rejection)] =
+	&[("PSW", Rejection::NotAuthorized), ("BSY", Rejection::Busy)];
+
+/// `#ERR` codes of commands without arguments, beyond the common ones.
+const NO_CODES: &[(&str, Rejection)] = &[];
+
+/// `#ERR` codes of commands addressing a relay, line, channel or sensor.
+const INDEX_CODES: &[(&str, Rejection)] = &[("IDX", Rejection::InvalidIndex)];
+
+/// `#ERR` codes of commands taking a value to check.
+const ARGUMENT_CODES: &[(&str, Rejection)] = &[("ARG", Rejection::InvalidArgument)];
+
+const RELAY_CODES: &[(&str, Rejection)] = &[
+	("IDX", Rejection::InvalidIndex),
+	("ACT", Rejection::InvalidArgument),
+	("DLY", Rejection::InvalidArgument),
+];
+
+const PULSE_CODES: &[(&str, Rejection)] = &[
+	("IDX", Rejection::InvalidIndex),
+	("CNT", Rejection::InvalidArgument),
+	("FRQ", Rejection::InvalidArgument),
+];
+
+type Frame = Result<Vec<String>>;
+type Terminator = Arc<dyn Fn(&[String]) -> bool + Send + Sync + 'static>;
+
+/// Where the reply frames of a command are delivered.
+enum Reply {
+	Single {
+		tx: oneshot::Sender<Frame>,
+		/// First field of the reply, for typed commands. Lets a reply the caller gave up on
+		/// be told apart from one meant for a later command.
+		header: Option<&'static str>,
+	},
+	Multi {
+		tx: mpsc::UnboundedSender<Frame>,
+		until: Terminator,
+	},
+}
+
+/// A command on its way to the device together with the channel its reply is delivered to.
+struct Request {
+	cmd: Box<dyn JoinParts + Send + 'static>,
+	reply: Reply,
+}
+
+/// The event format version carried by a reply to `$KE,MSG,VER`.
+fn announced_event_format(reply: &[String]) -> Option<u32> {
+	match as_match!(reply) {
+		["#MSG", "VER", version] => version.parse().ok(),
+		_ => None,
+	}
+}
+
+/// Whether `msg` can be the reply to a command answered with `header`.
+fn answers(msg: &Frame, header: &str) -> bool {
+	match msg {
+		Ok(parts) => parts
+			.first()
+			.is_some_and(|got| got == header || got == "#ERR"),
+		// Can't tell; taken to be the reply.
+		Err(_) => true,
+	}
+}
+
+/// Hands a reply frame to the oldest pending command.
+///
+/// A multi-frame reply stays at the front of the queue until its terminator is seen or its
+/// caller stops listening, in which case the frame moves on to the next pending command.
+/// A single-frame command whose caller gave up, e.g. on the command timeout, still takes a
+/// frame that fits its reply, so a late reply is dropped rather than handed to the next
+/// caller. A frame that doesn't fit means the device never answered; the command is then
+/// skipped and the frame moves on.
+fn route(pending: &mut VecDeque<Reply>, mut msg: Frame) {
+	while let Some(reply) = pending.pop_front() {
+		match reply {
+			Reply::Single { tx, header } => {
+				if tx.is_closed() && header.is_some_and(|header| !answers(&msg, header)) {
+					continue;
+				}
+				let _ = tx.send(msg);
+				return;
+			}
+			Reply::Multi { tx, until } => {
+				let done = msg.as_deref().map_or(true, |parts| until(parts));
+				match tx.send(msg) {
+					Ok(()) if !done => {
+						pending.push_front(Reply::Multi { tx, until });
+						return;
+					}
+					Ok(()) => return,
+					Err(SendError(rejected)) => msg = rejected,
+				}
+			}
+		}
+	}
+}
+
+/// Relay commands that return as soon as they are queued, see
+/// [`StreamGateway::detached_relays`].
+///
+/// Commands go out in the order they were issued, behind or between those of other callers.
+#[derive(Debug, Clone)]
+pub struct DetachedRelays {
+	tx: mpsc::UnboundedSender<Box<dyn JoinParts + Send + 'static>>,
+	audit: Option<Arc<dyn Audit>>,
 }
 
-#[derive(Debug)]
-pub struct StreamGateway {
-	cmd_tx: Sender<Box<dyn JoinParts + Send + 'static>>,
-	cmd_rx: Mutex<Receiver<Result<Vec<String>>>>,
-	events: Broadcaster<Event>,
-}
+impl DetachedRelays {
+	/// Queues a relay command. Fails only if the gateway is gone.
+	pub fn relay(&self, relay: u32, action: RelayAction, delay: Option<ClickDelay>) -> Result<()> {
+		let cmd = match delay {
+			None => ("$KE", "REL", relay, action).boxed(),
+			Some(delay) => ("$KE", "REL", relay, action, delay).boxed(),
+		};
+
+		if let Some(audit) = &self.audit {
+			audit.on_command(&CommandRecord {
+				command: cmd.join_parts(),
+				at: SystemTime::now(),
+			});
+		}
+
+		self.tx.send(cmd).map_err(|_| Error::Send)
+	}
+}
+
+/// Pings a [`StreamGateway`] in the background; see [`StreamGateway::spawn_keepalive`].
+///
+/// The task stops when the handle is dropped.
+#[derive(Debug)]
+pub struct KeepaliveHandle {
+	failures: watch::Receiver<u32>,
+	task: tokio::task::AbortHandle,
+	/// The gateway's record of the running keepalive, cleared when the handle drops.
+	interval: Arc<std::sync::Mutex<Option<Duration>>>,
+}
+
+impl KeepaliveHandle {
+	/// Number of pings in a row that failed or went unanswered, back to 0 after one
+	/// succeeds.
+	pub fn failures(&self) -> watch::Receiver<u32> {
+		self.failures.clone()
+	}
+}
+
+impl Drop for KeepaliveHandle {
+	fn drop(&mut self) {
+		self.task.abort();
+		*self.interval.lock().unwrap() = None;
+	}
+}
+
+/// Fails unless a keepalive every `interval` restarts a command watchdog of `window` in
+/// time.
+fn check_keepalive(interval: Duration, window: Duration) -> Result<()> {
+	if interval < window {
+		return Ok(());
+	}
+
+	Err(Error::InvalidPayload(format!(
+		"A keepalive every {interval:?} lets a command watchdog of {window:?} fire"
+	)))
+}
+
+#[derive(Debug)]
+pub struct StreamGateway {
+	cmd_tx: Sender<Request>,
+	/// Never read from; only used to hand out new subscriptions. The reader task owns the
+	/// sending half, so subscribers see the channel close once the connection is gone.
+	events: BroadcastReceiver<Event>,
+	shutdown: CancellationToken,
+	caps: std::sync::RwLock<Capabilities>,
+	lenient: bool,
+	strict: bool,
+	raw_lines: Option<BroadcastReceiver<Bytes>>,
+	event_parts: Option<BroadcastReceiver<RawEvent>>,
+	last_heartbeat: Arc<std::sync::Mutex<Option<Instant>>>,
+	watchdog: std::sync::Mutex<Option<Duration>>,
+	/// Interval of the keepalive last spawned, while its handle is alive.
+	keepalive: Arc<std::sync::Mutex<Option<Duration>>>,
+	command_timeout: Option<Duration>,
+	session: Arc<std::sync::Mutex<Session>>,
+	state: watch::Receiver<ConnectionState>,
+	audit: Option<Arc<dyn Audit>>,
+	parsers: Arc<std::sync::RwLock<EventParsers>>,
+}
+
+impl StreamGateway {
+	pub fn connect<T>(stream: T) -> Self
+	where
+		T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+	{
+		Self::start(stream, &GatewayOptions::default())
+	}
+
+	/// Like [`connect`](Self::connect), wrapped in an [`Arc`] to share with handles such as
+	/// [`Relay`](crate::Relay) and [`InputLine`](crate::InputLine).
+	pub fn connect_shared<T>(stream: T) -> Arc<Self>
+	where
+		T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+	{
+		Arc::new(Self::connect(stream))
+	}
+
+	/// Like [`connect`](Self::connect), but takes the connection-level settings from
+	/// `opts`. The handshake settings are left to the caller.
+	pub fn connect_with<T>(stream: T, opts: &GatewayOptions) -> Result<Self>
+	where
+		T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+	{
+		opts.validate()?;
+		Ok(Self::start(stream, opts))
+	}
+
+	/// Like [`connect_with`](Self::connect_with), and applies the TCP settings of `opts`
+	/// to the socket first.
+	pub fn connect_tcp(stream: TcpStream, opts: &GatewayOptions) -> Result<Self> {
+		configure_tcp(&stream, opts)?;
+		Self::connect_with(stream, opts)
+	}
+
+	/// Connects with `connect`, and calls it again whenever the connection drops.
+	///
+	/// Attempts back off exponentially, from 100 ms up to 10 s between them. The password
+	/// last accepted by [`authorize`](Gateway::authorize) and the event kinds turned on
+	/// with [`cfg_event`](Gateway::cfg_event) are applied again on every new connection.
+	/// Commands that were waiting for a reply, or are issued while reconnecting, fail with
+	/// [`Error::Closed`]. Watch [`connection_state`](Self::connection_state) to follow along.
+	pub async fn connect_reconnecting<F, Fut, T>(
+		mut connect: F,
+		opts: &GatewayOptions,
+	) -> Result<Self>
+	where
+		F: FnMut() -> Fut + Send + 'static,
+		Fut: Future<Output = Result<T>> + Send + 'static,
+		T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+	{
+		opts.validate()?;
+
+		let stream = connect().await?;
+		let reconnect: Reconnect<T> = Box::new(move || connect().boxed());
+		let (gw, reader) = Self::build(stream, opts, Some(reconnect));
+		spawn(opts.label.as_deref(), reader);
+		Ok(gw)
+	}
+
+	/// Like [`connect`](Self::connect), but hands the reader task to `spawn` instead of
+	/// `tokio::spawn`, e.g. to run it under a supervisor. The gateway only works while that
+	/// future is being polled.
+	pub fn connect_with_spawner<T, S>(stream: T, spawn: S) -> Self
+	where
+		T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+		S: FnOnce(BoxFuture<'static, ()>),
+	{
+		let (gw, reader) = Self::build(stream, &GatewayOptions::default(), None);
+		spawn(reader.boxed());
+		gw
+	}
+
+	fn start<T>(stream: T, opts: &GatewayOptions) -> Self
+	where
+		T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+	{
+		let (gw, reader) = Self::build(stream, opts, None);
+		spawn(opts.label.as_deref(), reader);
+		gw
+	}
+
+	/// Creates the gateway together with the reader future that drives its stream. With
+	/// `reconnect`, a stream that ends is replaced by a new one instead of stopping the
+	/// gateway.
+	fn build<T>(
+		stream: T,
+		opts: &GatewayOptions,
+		reconnect: Option<Reconnect<T>>,
+	) -> (Self, impl Future<Output = ()> + Send + 'static)
+	where
+		T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+	{
+		let (cmd_tx, cmd_rx) = mpsc::channel::<Request>(1);
+		let (event_tx, events) = broadcast::channel(opts.capacity);
+		let (state_tx, state) = watch::channel(ConnectionState::Connected);
+
+		let shutdown = CancellationToken::new();
+		let last_heartbeat = Arc::new(std::sync::Mutex::new(None));
+		let session = Arc::new(std::sync::Mutex::new(Session::default()));
+		let parsers = Arc::new(std::sync::RwLock::new(EventParsers::default()));
+
+		let (tap, raw_lines) = if opts.raw_tap {
+			let (tap, raw_lines) = broadcast::channel(opts.capacity);
+			(Some(tap), Some(raw_lines))
+		} else {
+			(None, None)
+		};
+
+		let (parts_tx, event_parts) = if opts.event_parts {
+			let (tx, rx) = broadcast::channel(opts.capacity);
+			(Some(tx), Some(rx))
+		} else {
+			(None, None)
+		};
+
+		let mut reader = Reader {
+			cmd_rx,
+			event_tx,
+			parts_tx,
+			tap,
+			cancelled: shutdown.clone(),
+			heartbeat: last_heartbeat.clone(),
+			event_interval: opts.event_interval,
+			event_format: 1,
+			line_events: HashMap::new(),
+			parsers: parsers.clone(),
+		};
+
+		let setup = session.clone();
+		let task = async move {
+			let mut stream = stream;
+			let mut reconnect = reconnect;
+
+			loop {
+				let commands = setup.lock().unwrap().commands();
+				let exit = reader.serve(stream, commands).await;
+				let (Exit::Disconnected, Some(connect)) = (exit, reconnect.as_mut()) else {
+					break;
+				};
+
+				let _ = state_tx.send(ConnectionState::Reconnecting);
+				let Some(next) = reader.reconnect(connect).await else {
+					break;
+				};
+				stream = next;
+				let _ = state_tx.send(ConnectionState::Connected);
+			}
+
+			let _ = state_tx.send(ConnectionState::Closed);
+		};
+
+		let gw = Self {
+			cmd_tx,
+			events,
+			shutdown,
+			caps: std::sync::RwLock::new(opts.capabilities.clone()),
+			lenient: opts.lenient,
+			strict: opts.strict,
+			raw_lines,
+			event_parts,
+			last_heartbeat,
+			watchdog: std::sync::Mutex::new(None),
+			keepalive: Arc::default(),
+			command_timeout: opts.command_timeout,
+			session,
+			state,
+			audit: opts.audit.clone(),
+			parsers,
+		};
+
+		(gw, task)
+	}
+
+	/// A handle for relay commands that don't wait for their acknowledgement.
+	///
+	/// The acknowledgements are still read, in a background task; failures, `#ERR` replies
+	/// included, go to `on_error` instead of the caller. The command timeout doesn't apply.
+	pub fn detached_relays<F>(&self, on_error: F) -> DetachedRelays
+	where
+		F: Fn(Error) + Send + Sync + 'static,
+	{
+		let (tx, mut rx) = mpsc::unbounded_channel::<Box<dyn JoinParts + Send + 'static>>();
+		let cmd_tx = self.cmd_tx.clone();
+
+		tokio::spawn(async move {
+			let mut acks = FuturesUnordered::new();
+			let check = |ack: std::result::Result<Frame, _>| {
+				let res = ack
+					.unwrap_or(Err(Error::Closed))
+					.and_then(|reply| match as_match!(reply) {
+						["#REL", "OK"] => Ok(()),
+						["#ERR", ..] => Err(rejection(&reply, RELAY_CODES)),
+						_ => Err(Error::Unrecognized(reply)),
+					});
+				if let Err(err) = res {
+					on_error(err);
+				}
+			};
+
+			loop {
+				tokio::select! {
+					cmd = rx.recv() => {
+						let Some(cmd) = cmd else {
+							break;
+						};
+						let (reply, ack) = oneshot::channel();
+						let req = Request {
+							cmd,
+							reply: Reply::Single {
+								tx: reply,
+								header: Some("#REL"),
+							},
+						};
+						if cmd_tx.send(req).await.is_err() {
+							on_error(Error::Send);
+							break;
+						}
+						acks.push(ack);
+					},
+					Some(ack) = acks.next() => check(ack),
+				}
+			}
+
+			while let Some(ack) = acks.next().await {
+				check(ack);
+			}
+		});
+
+		DetachedRelays {
+			tx,
+			audit: self.audit.clone(),
+		}
+	}
+
+	/// Pings the module every `interval`, so a connection silently dropped by a NAT or
+	/// firewall shows up without waiting for a command to fail.
+	///
+	/// A ping counts as failed unless it is answered within `interval`. Watch
+	/// [`KeepaliveHandle::failures`] to decide when to reconnect. The task doesn't keep the
+	/// connection open and ends along with it.
+	///
+	/// With a [`command_watchdog`](Self::command_watchdog) set, an `interval` that isn't
+	/// below its window is rejected with [`Error::InvalidPayload`]; keep it well below.
+	pub fn spawn_keepalive(&self, interval: Duration) -> Result<KeepaliveHandle> {
+		if let Some(window) = self.command_watchdog() {
+			check_keepalive(interval, window)?;
+		}
+		*self.keepalive.lock().unwrap() = Some(interval);
+
+		let (failures_tx, failures) = watch::channel(0);
+		let cmd_tx = self.cmd_tx.downgrade();
+
+		let task = tokio::spawn(async move {
+			let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+			ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+			loop {
+				ticks.tick().await;
+				let Some(cmd_tx) = cmd_tx.upgrade() else {
+					break;
+				};
+
+				let (reply, ack) = oneshot::channel();
+				let req = Request {
+					cmd: ("$KE",).boxed(),
+					reply: Reply::Single {
+						tx: reply,
+						header: Some("#OK"),
+					},
+				};
+				let answered = cmd_tx.send(req).await.is_ok()
+					&& matches!(
+						tokio::time::timeout(interval, ack).await,
+						Ok(Ok(Ok(reply))) if reply.first().is_some_and(|hdr| hdr == "#OK")
+					);
+				drop(cmd_tx);
+
+				failures_tx.send_modify(|failures| match answered {
+					true => *failures = 0,
+					false => *failures += 1,
+				});
+			}
+		});
+
+		Ok(KeepaliveHandle {
+			failures,
+			task: task.abort_handle(),
+			interval: self.keepalive.clone(),
+		})
+	}
+
+	/// Follows the state of the connection, see
+	/// [`connect_reconnecting`](Self::connect_reconnecting).
+	pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+		self.state.clone()
+	}
+
+	pub fn capabilities(&self) -> Capabilities {
+		self.caps.read().unwrap().clone()
+	}
+
+	/// Replaces the capabilities with those derived from the firmware's command listing.
+	pub async fn detect_capabilities(&self) -> Result<Capabilities> {
+		let caps = Capabilities::from_commands(&self.supported_commands().await?);
+		*self.caps.write().unwrap() = caps.clone();
+		Ok(caps)
+	}
+
+	/// Subscribes to the lines received from the module exactly as they arrived.
+	///
+	/// Returns `None` unless the gateway was connected with
+	/// [`GatewayOptions::with_raw_tap`].
+	pub fn raw_lines(&self) -> Option<BroadcastReceiver<Bytes>> {
+		self.raw_lines.as_ref().map(BroadcastReceiver::resubscribe)
+	}
+
+	/// Parses `#M,<kind>,...` frames of a kind the crate doesn't know with `parse`, which gets
+	/// the fields after the kind. The result is delivered to subscribers as
+	/// [`Event::Custom`] alongside the built-in events.
+	///
+	/// Kinds the crate already parses never reach `parse`. Frames it fails on are dropped
+	/// like any other malformed event.
+	pub fn register_event_parser<T, F>(&self, kind: &str, parse: F)
+	where
+		T: Any + Send + Sync,
+		F: Fn(&[String]) -> Result<T> + Send + Sync + 'static,
+	{
+		self.parsers.write().unwrap().register(kind, parse);
+	}
+
+	/// Subscribes to events together with the fields they were parsed from.
+	///
+	/// Returns `None` unless the gateway was connected with
+	/// [`GatewayOptions::with_event_parts`].
+	pub fn subscribe_with_parts(&self) -> Option<BroadcastReceiver<RawEvent>> {
+		self
+			.event_parts
+			.as_ref()
+			.map(BroadcastReceiver::resubscribe)
+	}
+
+	/// Connects over TCP with default options. Use [`connect`](Self::connect) for other
+	/// kinds of streams.
+	pub async fn connect_addr<A>(addr: A) -> Result<Self>
+	where
+		A: ToSocketAddrs,
+	{
+		Self::open(addr, GatewayOptions::default()).await
+	}
+
+	/// Connects over TCP and wraps the connection in TLS, verifying the server as
+	/// `server_name` with `config`.
+	///
+	/// The modules themselves only speak plain TCP; this is for one reached through a
+	/// TLS-terminating tunnel or proxy.
+	#[cfg(feature = "tls")]
+	pub async fn connect_tls<A>(
+		addr: A,
+		server_name: &str,
+		config: Arc<tokio_rustls::rustls::ClientConfig>,
+	) -> Result<Self>
+	where
+		A: ToSocketAddrs,
+	{
+		use tokio_rustls::rustls::pki_types::ServerName;
+
+		let server_name = ServerName::try_from(server_name.to_owned())
+			.map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+		let stream = TcpStream::connect(addr).await?;
+		let connector = tokio_rustls::TlsConnector::from(config);
+		let stream = connector.connect(server_name, stream).await?;
+		Ok(Self::connect(stream))
+	}
+
+	/// Opens the serial port at `path` (e.g. `/dev/ttyUSB0` or `COM3`) with the given baud
+	/// rate, for module variants that speak the protocol over a serial line.
+	#[cfg(feature = "serial")]
+	pub fn connect_serial(path: &str, baud_rate: u32) -> Result<Self> {
+		use tokio_serial::SerialPortBuilderExt;
+
+		let port = tokio_serial::new(path, baud_rate)
+			.open_native_async()
+			.map_err(std::io::Error::from)?;
+		Ok(Self::connect(port))
+	}
+
+	/// Connects over TCP and runs the handshake configured in `opts`: authorization first,
+	/// then enabling each requested event kind.
+	pub async fn open<A>(addr: A, opts: GatewayOptions) -> Result<Self>
+	where
+		A: ToSocketAddrs,
+	{
+		opts.validate()?;
+
+		let stream = match opts.connect_timeout {
+			Some(timeout) => tokio::time::timeout(timeout, TcpStream::connect(addr))
+				.await
+				.map_err(|_| Error::Timeout)??,
+			None => TcpStream::connect(addr).await?,
+		};
+
+		configure_tcp(&stream, &opts)?;
+
+		let mut opts = opts;
+		if opts.label.is_none() {
+			opts.label = stream.peer_addr().ok().map(|addr| addr.to_string());
+		}
+
+		let gw = Self::start(stream, &opts);
+		gw.handshake(&opts).await?;
+		Ok(gw)
+	}
+
+	/// Like [`open`](Self::open), over an already established stream such as a serial port
+	/// or a tunnel. The TCP settings of `opts` don't apply.
+	pub async fn open_stream<T>(stream: T, opts: GatewayOptions) -> Result<Self>
+	where
+		T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+	{
+		let gw = Self::connect_with(stream, &opts)?;
+		gw.handshake(&opts).await?;
+		Ok(gw)
+	}
+
+	/// Like [`open`](Self::open), wrapped in an [`Arc`] to share with handles.
+	pub async fn open_shared<A>(addr: A, opts: GatewayOptions) -> Result<Arc<Self>>
+	where
+		A: ToSocketAddrs,
+	{
+		Self::open(addr, opts).await.map(Arc::new)
+	}
+
+	async fn handshake(&self, opts: &GatewayOptions) -> Result<()> {
+		if let Some(pwd) = &opts.password {
+			self.authorize(pwd).await?;
+		}
+
+		for kind in &opts.events {
+			self.cfg_event(*kind, true).await?;
+		}
+
+		Ok(())
+	}
+
+	/// Logs out of the device and closes the connection.
+	///
+	/// Firmware without a logout command answers `#ERR`, and some drop the link without
+	/// answering at all; both are treated as a successful logout. A device that stays silent
+	/// is given up on after [`LOGOUT_TIMEOUT`]. Once this returns, the stream is closed and
+	/// every further command fails, even if the logout itself failed.
+	pub async fn close(&self) -> Result<()> {
+		let logout = tokio::time::timeout(LOGOUT_TIMEOUT, self.exchange(("$KE", "QUIT")))
+			.await
+			.unwrap_or(Err(Error::Timeout));
+
+		self.shutdown.cancel();
+		self.cmd_tx.closed().await;
+
+		match logout {
+			Ok(_) | Err(Error::Closed) => Ok(()),
+			Err(err) => Err(err),
+		}
+	}
+
+	/// When the device last sent [`Event::Heartbeat`], or `None` if it never did.
+	///
+	/// Lets a health check see that the device is alive without sending a
+	/// [`ping`](Gateway::ping).
+	pub fn last_heartbeat(&self) -> Option<Instant> {
+		*self.last_heartbeat.lock().unwrap()
+	}
+
+	/// The command watchdog window last set through this gateway, so a keepalive can be
+	/// scheduled to stay under it. See [`Gateway::set_command_watchdog`].
+	pub fn command_watchdog(&self) -> Option<Duration> {
+		*self.watchdog.lock().unwrap()
+	}
+
+	pub fn is_closed(&self) -> bool {
+		self.cmd_tx.is_closed()
+	}
+
+	/// Sends `$KE` followed by `parts` and returns the reply as-is.
+	///
+	/// This is an escape hatch for commands the typed [`Gateway`] methods don't cover yet.
+	pub async fn raw_command(&self, parts: Vec<String>) -> Result<Vec<String>> {
+		self
+			.send_command(Command::builder().extend(parts).build())
+			.await
+	}
+
+	/// Sends a command built with [`Command::builder`] and returns the reply as-is.
+	pub async fn send_command(&self, cmd: Command) -> Result<Vec<String>> {
+		self.exchange(cmd).await
+	}
+
+	/// Like [`raw_command`](Self::raw_command), but collects reply frames until `until`
+	/// matches one of them.
+	///
+	/// The terminating frame is included in the result. If the device goes quiet for
+	/// longer than [`MULTI_REPLY_TIMEOUT`] before the terminator arrives, the frames
+	/// collected so far are returned, or [`Error::Timeout`] if there are none.
+	pub async fn raw_command_multi<F>(&self, parts: Vec<String>, until: F) -> Result<Vec<Vec<String>>>
+	where
+		F: Fn(&[String]) -> bool + Send + Sync + 'static,
+	{
+		let cmd = Command::builder().extend(parts).build();
+		self.exchange_multi(cmd, until).await
+	}
+
+	/// Starts device-side sampling of an ADC channel at `rate_hz` and yields the samples as
+	/// they arrive.
+	///
+	/// Sampling is switched off again when the stream is dropped. A subscriber that falls
+	/// behind gets [`Error::EventsLagged`] with the number of events it missed, and goes on
+	/// with the oldest one still queued; the stream ends when the connection is gone.
+	pub async fn adc_stream(
+		&self,
+		channel: u32,
+		rate_hz: u32,
+	) -> Result<impl Stream<Item = Result<u16>> + Send + 'static> {
+		if rate_hz == 0 {
+			return Err(Error::InvalidPayload(
+				"The sampling rate must be at least 1 Hz".into(),
+			));
+		}
+
+		// Subscribe first so the samples right behind the reply aren't missed.
+		let sub = self.subscibe();
+		let reply = self
+			.request(("$KE", "ADC", channel, "STR", rate_hz), "#ADC")
+			.await?;
+		match as_match!(reply) {
+			["#ADC", "OK", ..] => self.fields(&reply, 2)?,
+			["#ERR", ..] => return Err(self.rejected(&reply, INDEX_CODES)),
+			_ => return Err(self.unknown(&reply)),
+		}
+
+		let guard = StopSampling {
+			cmd_tx: self.cmd_tx.clone(),
+			channel,
+		};
+
+		Ok(futures::stream::unfold(
+			(sub, guard),
+			move |(mut sub, guard)| async move {
+				loop {
+					match recv_event(&mut sub).await {
+						Ok(Event::Adc {
+							channel: cid,
+							value,
+						}) if cid == channel => return Some((Ok(value), (sub, guard))),
+						Ok(_) => (),
+						Err(Error::Closed) => return None,
+						Err(err) => return Some((Err(err), (sub, guard))),
+					}
+				}
+			},
+		))
+	}
+
+	/// Turns the given event kinds off until the returned guard is dropped, e.g. to keep a
+	/// bulk reconfiguration from flooding subscribers.
+	///
+	/// Dropping the guard can't wait for the device, so it spawns a task that turns the
+	/// kinds back on in the background with [`cfg_event`](Gateway::cfg_event); commands sent
+	/// right after the drop may reach the device first. The kinds are turned on again
+	/// whether or not they were on before. A kind the device refuses to turn back on stays
+	/// off, on later connections as well.
+	pub async fn suppress_events(self: &Arc<Self>, kinds: &[EventKind]) -> Result<SuppressedEvents> {
+		let mut guard = SuppressedEvents {
+			gw: Arc::downgrade(self),
+			kinds: Vec::with_capacity(kinds.len()),
+		};
+
+		// Kinds turned off before a failure are restored when the guard drops here.
+		for &kind in kinds {
+			self.cfg_event(kind, false).await?;
+			guard.kinds.push(kind);
+		}
+
+		Ok(guard)
+	}
+
+	/// Checks the number of fields of a reply whose pattern matched its first `count`.
+	/// Trailing fields make the reply [unknown](Self::unknown), or unexpected if the gateway
+	/// is strict.
+	fn fields(&self, reply: &[String], count: usize) -> Result<()> {
+		match reply.len() == count {
+			true => Ok(()),
+			false if self.strict => Err(self.unexpected(reply)),
+			false => Err(self.unknown(reply)),
+		}
+	}
+
+	/// The error for an `#ERR` reply. A bare `#ERR` is a [`Error::SyntaxError`]; a code after
+	/// it is looked up in `codes`, the ones the command is known to answer with, and then in
+	/// the codes any command may answer with.
+	fn rejected(&self, reply: &[String], codes: &[(&str, Rejection)]) -> Error {
+		rejection(reply, codes)
+	}
+
+	/// The error for a reply a typed method doesn't recognize.
+	fn unknown(&self, reply: &[String]) -> Error {
+		if self.lenient {
+			Error::Unrecognized(reply.to_vec())
+		} else {
+			Error::UnknownMessage(reply.to_vec())
+		}
+	}
+
+	/// The error for a reply to the right command that doesn't fit it, e.g. one about
+	/// another relay.
+	fn unexpected(&self, reply: &[String]) -> Error {
+		Error::UnexpectedMessage(reply.to_vec())
+	}
+
+	/// Sends a typed command whose reply starts with `header`.
+	///
+	/// `#ERR` is passed on for the caller to map. Any other header means the reply belongs to
+	/// a different command and is reported as [`Error::UnexpectedHeader`].
+	async fn request<T>(&self, cmd: T, header: &'static str) -> Result<Vec<String>>
+	where
+		T: JoinParts + Send + 'static,
+	{
+		let reply = self.exchange_expecting(cmd, Some(header)).await?;
+		match reply.first() {
+			Some(got) if got == header || got == "#ERR" => Ok(reply),
+			_ if self.lenient => Err(Error::Unrecognized(reply)),
+			got => Err(Error::UnexpectedHeader {
+				expected: header,
+				got: got.cloned().unwrap_or_default(),
+			}),
+		}
+	}
+
+	/// Like [`request`](Self::request), for a command that changes the state of the module.
+	/// The command is reported to the audit hook first.
+	async fn change<T>(&self, cmd: T, header: &'static str) -> Result<Vec<String>>
+	where
+		T: JoinParts + Send + 'static,
+	{
+		self.audit(cmd.join_parts());
+		self.request(cmd, header).await
+	}
+
+	fn audit(&self, command: String) {
+		if let Some(audit) = &self.audit {
+			audit.on_command(&CommandRecord {
+				command,
+				at: SystemTime::now(),
+			});
+		}
+	}
+
+	async fn exchange<T>(&self, cmd: T) -> Result<Vec<String>>
+	where
+		T: JoinParts + Send + 'static,
+	{
+		self.exchange_expecting(cmd, None).await
+	}
+
+	/// Like [`exchange`](Self::exchange), for a command whose reply starts with `header`.
+	async fn exchange_expecting<T>(&self, cmd: T, header: Option<&'static str>) -> Result<Vec<String>>
+	where
+		T: JoinParts + Send + 'static,
+	{
+		let (tx, rx) = oneshot::channel();
+		let req = Request {
+			cmd: cmd.boxed(),
+			reply: Reply::Single { tx, header },
+		};
+
+		let exchange = async {
+			self.cmd_tx.send(req).await.map_err(|_| Error::Send)?;
+			rx.await.map_err(|_| Error::Closed)?
+		};
+
+		match self.command_timeout {
+			Some(timeout) => tokio::time::timeout(timeout, exchange)
+				.await
+				.map_err(|_| Error::Timeout)?,
+			None => exchange.await,
+		}
+	}
+
+	async fn exchange_multi<T, F>(&self, cmd: T, until: F) -> Result<Vec<Vec<String>>>
+	where
+		T: JoinParts + Send + 'static,
+		F: Fn(&[String]) -> bool + Send + Sync + 'static,
+	{
+		let until: Terminator = Arc::new(until);
+		let (tx, mut rx) = mpsc::unbounded_channel();
+		let req = Request {
+			cmd: cmd.boxed(),
+			reply: Reply::Multi {
+				tx,
+				until: until.clone(),
+			},
+		};
+
+		self.cmd_tx.send(req).await.map_err(|_| Error::Send)?;
+
+		let mut frames = Vec::new();
+		loop {
+			match tokio::time::timeout(MULTI_REPLY_TIMEOUT, rx.recv()).await {
+				Ok(Some(Ok(msg))) => {
+					let done = until(&msg);
+					frames.push(msg);
+					if done {
+						return Ok(frames);
+					}
+				}
+				Ok(Some(Err(err))) => return Err(err),
+				Ok(None) => return Err(Error::Closed),
+				Err(_) if frames.is_empty() => return Err(Error::Timeout),
+				Err(_) => return Ok(frames),
+			}
+		}
+	}
+}
+
+/// Switches device-side sampling off when an [`StreamGateway::adc_stream`] is dropped.
+struct StopSampling {
+	cmd_tx: Sender<Request>,
+	channel: u32,
+}
+
+impl Drop for StopSampling {
+	fn drop(&mut self) {
+		// Nobody waits for the reply; it is still routed so later replies stay in order.
+		let req = Request {
+			cmd: ("$KE", "ADC", self.channel, "STR", "OFF").boxed(),
+			reply: Reply::Single {
+				tx: oneshot::channel().0,
+				header: Some("#ADC"),
+			},
+		};
+
+		if let Err(TrySendError::Full(req)) = self.cmd_tx.try_send(req) {
+			if let Ok(rt) = tokio::runtime::Handle::try_current() {
+				let cmd_tx = self.cmd_tx.clone();
+				rt.spawn(async move {
+					let _ = cmd_tx.send(req).await;
+				});
+			}
+		}
+	}
+}
+
+/// Keeps event kinds off; see [`StreamGateway::suppress_events`].
+#[derive(Debug)]
+pub struct SuppressedEvents {
+	gw: Weak<StreamGateway>,
+	kinds: Vec<EventKind>,
+}
+
+impl Drop for SuppressedEvents {
+	fn drop(&mut self) {
+		let kinds = std::mem::take(&mut self.kinds);
+		let Ok(rt) = tokio::runtime::Handle::try_current() else {
+			return;
+		};
+
+		let gw = self.gw.clone();
+		rt.spawn(async move {
+			for kind in kinds {
+				let Some(gw) = gw.upgrade() else {
+					break;
+				};
+				let _ = gw.cfg_event(kind, true).await;
+			}
+		});
+	}
+}
+
+/// Produces a new stream to the module for [`StreamGateway::connect_reconnecting`].
+type Reconnect<T> = Box<dyn FnMut() -> BoxFuture<'static, Result<T>> + Send>;
+
+/// First delay between reconnection attempts, doubled after each failure.
+const RECONNECT_BACKOFF_MIN: Duration = Duration::from_millis(100);
+
+/// Longest delay between reconnection attempts.
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(10);
+
+/// Whether a [`StreamGateway`] has a connection to the module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+	Connected,
+	/// The connection dropped and a new one is being established, see
+	/// [`StreamGateway::connect_reconnecting`].
+	Reconnecting,
+	/// The gateway stopped for good.
+	Closed,
+}
+
+/// What a new connection needs to be brought to the state of the one it replaces.
+#[derive(Debug, Default)]
+struct Session {
+	/// Last password accepted by the module.
+	password: Option<String>,
+	/// Event kinds turned on, and not off again, through the gateway.
+	events: Vec<EventKind>,
+}
+
+impl Session {
+	fn event(&mut self, kind: EventKind, enabled: bool) {
+		self.events.retain(|&other| other != kind);
+		if enabled {
+			self.events.push(kind);
+		}
+	}
+
+	/// The commands to send on a new connection. Nobody waits for their replies.
+	fn commands(&self) -> Vec<Request> {
+		let request = |cmd: Box<dyn JoinParts + Send + 'static>, header| Request {
+			cmd,
+			reply: Reply::Single {
+				tx: oneshot::channel().0,
+				header: Some(header),
+			},
+		};
+		let password = self
+			.password
+			.iter()
+			.map(|pwd| request(("$KE", "PSW", "SET", pwd.clone()).boxed(), "#PSW"));
+		let events = self
+			.events
+			.iter()
+			.map(|&kind| request(("$KE", "MSG", "S", kind, "SET", "ON").boxed(), "#MSG"));
+		password.chain(events).collect()
+	}
+}
+
+/// Why [`Reader::serve`] stopped.
+enum Exit {
+	/// The gateway is gone or was closed.
+	Shutdown,
+	/// The stream ended or failed.
+	Disconnected,
+}
+
+/// The state of the reader task that outlives a single connection.
+struct Reader {
+	cmd_rx: mpsc::Receiver<Request>,
+	event_tx: broadcast::Sender<Event>,
+	parts_tx: Option<broadcast::Sender<RawEvent>>,
+	tap: Option<broadcast::Sender<Bytes>>,
+	cancelled: CancellationToken,
+	heartbeat: Arc<std::sync::Mutex<Option<Instant>>>,
+	event_interval: Option<Duration>,
+	/// Layout of `#M` frames. Picked up from the reply to `$KE,MSG,VER` here rather than by
+	/// the caller, so that events right behind the reply already parse correctly.
+	event_format: u32,
+	/// When each line last had an event let through, for `event_interval`.
+	line_events: HashMap<u32, Instant>,
+	parsers: Arc<std::sync::RwLock<EventParsers>>,
+}
+
+impl Reader {
+	/// Passes commands and replies over `stream` until it ends, after sending `setup`.
+	async fn serve<T>(&mut self, stream: T, setup: Vec<Request>) -> Exit
+	where
+		T: AsyncRead + AsyncWrite + Unpin,
+	{
+		let codec = match &self.tap {
+			Some(tap) => Codec::new().with_tap(tap.clone()),
+			None => Codec::new(),
+		};
+		let mut stream = Framed::new(stream, codec);
+		// Replies carry no command tag, so they are paired with commands in the order the
+		// commands were written. A reply that arrives while nothing is pending (e.g. the
+		// delayed original of a retried command) is stale and gets dropped instead of being
+		// handed to the next caller. Callers still waiting when the stream ends get
+		// `Error::Closed` as the queue is dropped.
+		let mut pending = VecDeque::new();
+		// `Framed` ends the stream once after any decoder error. An over-long line is only
+		// skipped by the codec, so reading goes on past that end.
+		let mut skipped_line = false;
+
+		// Nobody waits for the replies to the setup; they are still routed so later replies
+		// stay in order.
+		for Request { cmd, reply } in setup {
+			if stream.send(cmd).await.is_err() {
+				return Exit::Disconnected;
+			}
+			pending.push_back(reply);
+		}
+
+		loop {
+			tokio::select! {
+				biased;
+
+				msg = stream.next() => {
+					let Some(msg) = msg else {
+						if std::mem::take(&mut skipped_line) {
+							continue;
+						}
+						return Exit::Disconnected;
+					};
+
+					match msg.as_deref() {
+						Ok([ty, rest @ ..]) if is_event(ty) => {
+							let event = self.parsers.read().unwrap().parse(rest, self.event_format);
+							if let Ok(event) = event {
+								if event == Event::Heartbeat {
+									*self.heartbeat.lock().unwrap() = Some(Instant::now());
+								}
+								if let (Some(interval), Event::Ein { line, .. }) = (self.event_interval, &event) {
+									let now = Instant::now();
+									if self.line_events.get(line).is_some_and(|last| now - *last < interval) {
+										continue;
+									}
+									self.line_events.insert(*line, now);
+								}
+								if let Some(parts_tx) = &self.parts_tx {
+									let raw = RawEvent {
+										event: event.clone(),
+										parts: std::iter::once(ty).chain(rest).cloned().collect(),
+									};
+									let _ = parts_tx.send(raw);
+								}
+								// Having no subscribers isn't an error; the event is just dropped.
+								let _ = self.event_tx.send(event);
+							}
+						},
+						Ok(reply) => {
+							if let Some(version) = announced_event_format(reply) {
+								self.event_format = version;
+							}
+							route(&mut pending, msg);
+						},
+						Err(err) => {
+							skipped_line = matches!(err, Error::MaxLineLengthExceeded { .. });
+							route(&mut pending, msg);
+						},
+					}
+				},
+				req = self.cmd_rx.recv() => {
+					let Some(Request { cmd, reply }) = req else {
+						return Exit::Shutdown;
+					};
+
+					if stream.send(cmd).await.is_err() {
+						return Exit::Disconnected;
+					}
+					pending.push_back(reply);
+				},
+				_ = self.cancelled.cancelled() => {
+					let _ = stream.close().await;
+					return Exit::Shutdown;
+				}
+			}
+		}
+	}
+
+	/// Calls `connect` until it yields a stream, backing off exponentially between
+	/// attempts. Returns `None` once the gateway is gone or closed.
+	async fn reconnect<T>(&mut self, connect: &mut Reconnect<T>) -> Option<T> {
+		let mut backoff = RECONNECT_BACKOFF_MIN;
+
+		loop {
+			tokio::select! {
+				stream = connect() => {
+					if let Ok(stream) = stream {
+						return Some(stream);
+					}
+				},
+				_ = self.cancelled.cancelled() => return None,
+			}
+
+			let sleep = tokio::time::sleep(backoff);
+			tokio::pin!(sleep);
+			loop {
+				tokio::select! {
+					_ = &mut sleep => break,
+					// Commands can't be delivered meanwhile. Dropping them fails the calls with
+					// `Error::Closed` rather than leaving the callers waiting.
+					req = self.cmd_rx.recv() => {
+						req?;
+					},
+					_ = self.cancelled.cancelled() => return None,
+				}
+			}
+
+			backoff = (backoff * 2).min(RECONNECT_BACKOFF_MAX);
+		}
+	}
+}
+
+#[cfg(all(tokio_unstable, feature = "tracing"))]
+fn spawn<F>(label: Option<&str>, task: F)
+where
+	F: Future<Output = ()> + Send + 'static,
+{
+	tokio::task::Builder::new()
+		.name(label.unwrap_or("laurent-gateway"))
+		.spawn(task)
+		.expect("Failed to spawn gateway task");
+}
+
+#[cfg(not(all(tokio_unstable, feature = "tracing")))]
+fn spawn<F>(_label: Option<&str>, task: F)
+where
+	F: Future<Output = ()> + Send + 'static,
+{
+	tokio::spawn(task);
+}
+
+#[async_trait]
+impl Gateway for StreamGateway {
+	fn subscibe(&self) -> BroadcastReceiver<Event> {
+		self.events.resubscribe()
+	}
+
+	fn watch_connection(&self) -> Option<watch::Receiver<ConnectionState>> {
+		Some(self.state.clone())
+	}
+
+	async fn ping(&self) -> Result<()> {
+		let reply = self.request(("$KE",), "#OK").await?;
+		match as_match!(reply) {
+			["#OK", ..] => self.fields(&reply, 1),
+			["#ERR", ..] => Err(self.rejected(&reply, NO_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	async fn authorize(&self, pwd: &str) -> Result<()> {
+		let reply = self
+			.request(("$KE", "PSW", "SET", pwd.to_owned()), "#PSW")
+			.await?;
+		match as_match!(reply) {
+			["#PSW", "SET", "OK", ..] => {
+				self.fields(&reply, 3)?;
+				self.session.lock().unwrap().password = Some(pwd.to_owned());
+				Ok(())
+			}
+			["#PSW", "SET", "ERR", ..] => {
+				self.fields(&reply, 3)?;
+				Err(Error::Auth)
+			}
+			["#ERR", ..] => Err(self.rejected(&reply, NO_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	/// The module takes `$KE,PSW,NEW,<pwd>`. The audit hook sees the command with the
+	/// password masked, and reconnects log in with the new one.
+	async fn change_password(&self, new: &str) -> Result<()> {
+		self.audit("$KE,PSW,NEW,***".into());
+		let reply = self
+			.request(("$KE", "PSW", "NEW", new.to_owned()), "#PSW")
+			.await?;
+		match as_match!(reply) {
+			["#PSW", "NEW", "OK", ..] => {
+				self.fields(&reply, 3)?;
+				self.session.lock().unwrap().password = Some(new.to_owned());
+				Ok(())
+			}
+			["#PSW", "NEW", "ERR", ..] => {
+				self.fields(&reply, 3)?;
+				Err(Error::Auth)
+			}
+			["#ERR", ..] => Err(self.rejected(&reply, NO_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	async fn cfg_event(&self, kind: EventKind, enabled: bool) -> Result<()> {
+		let state = if enabled { "ON" } else { "OFF" };
+		let reply = self
+			.change(("$KE", "MSG", "S", kind, "SET", state), "#MSG")
+			.await?;
+		match as_match!(reply) {
+			["#MSG", "SET", "OK", ..] => {
+				self.fields(&reply, 3)?;
+				self.session.lock().unwrap().event(kind, enabled);
+				Ok(())
+			}
+			["#ERR", ..] => Err(self.rejected(&reply, NO_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	async fn relay(&self, relay: u32, action: RelayAction, delay: Option<ClickDelay>) -> Result<()> {
+		let reply = match delay {
+			None => self.change(("$KE", "REL", relay, action), "#REL").await?,
+			Some(delay) => {
+				self
+					.change(("$KE", "REL", relay, action, delay), "#REL")
+					.await?
+			}
+		};
+
+		match as_match!(reply) {
+			["#REL", "OK", ..] => self.fields(&reply, 2),
+			["#ERR", ..] => Err(self.rejected(&reply, RELAY_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	async fn relay_status(&self, relay: u32) -> Result<bool> {
+		let reply = self.request(("$KE", "RDR", relay), "#RDR").await?;
+		match as_match!(reply) {
+			["#RDR", rid, on, ..] if rid.parse::<u32>()? == relay => {
+				self.fields(&reply, 3)?;
+				Ok(on == "1")
+			}
+			["#RDR", _, _, ..] => {
+				self.fields(&reply, 3)?;
+				Err(self.unexpected(&reply))
+			}
+			["#ERR", ..] => Err(self.rejected(&reply, INDEX_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	/// The module answers with `#RDR,ALL` followed by one state per relay, so the length of
+	/// the result follows the module's relay count.
+	async fn read_all_relays(&self) -> Result<Vec<bool>> {
+		let reply = self.request(("$KE", "RDR", "ALL"), "#RDR").await?;
+		match as_match!(reply) {
+			["#RDR", "ALL", ref states @ ..] => Ok(states.iter().map(|&on| on == "1").collect()),
+			["#ERR", ..] => Err(self.rejected(&reply, NO_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	async fn line_signal(&self, line: u32) -> Result<Signal> {
+		let reply = self.request(("$KE", "RD", line), "#RD").await?;
+		match as_match!(reply) {
+			["#RD", lid, on, ..] if lid.parse::<u32>()? == line => {
+				self.fields(&reply, 3)?;
+				Ok(on.parse()?)
+			}
+			["#RD", _, _, ..] => {
+				self.fields(&reply, 3)?;
+				Err(self.unexpected(&reply))
+			}
+			["#ERR", ..] => Err(self.rejected(&reply, INDEX_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	/// The module answers `#FRQ,<line>,OFF` for a line that isn't in frequency mode.
+	async fn input_frequency(&self, line: u32) -> Result<f32> {
+		check_input(line)?;
+
+		let reply = self.request(("$KE", "FRQ", line), "#FRQ").await?;
+		match as_match!(reply) {
+			["#FRQ", lid, "OFF", ..] if lid.parse::<u32>()? == line => {
+				self.fields(&reply, 3)?;
+				Err(Error::InvalidPayload(format!(
+					"Input line {line} is not in frequency mode"
+				)))
+			}
+			["#FRQ", lid, hz, ..] if lid.parse::<u32>()? == line => {
+				self.fields(&reply, 3)?;
+				Ok(hz.parse()?)
+			}
+			["#FRQ", _, _, ..] => {
+				self.fields(&reply, 3)?;
+				Err(self.unexpected(&reply))
+			}
+			["#ERR", ..] => Err(self.rejected(&reply, INDEX_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	async fn read_counter(&self, line: u32) -> Result<u32> {
+		check_input(line)?;
+
+		let reply = self.request(("$KE", "CNT", line), "#CNT").await?;
+		match as_match!(reply) {
+			["#CNT", lid, count, ..] if lid.parse::<u32>()? == line => {
+				self.fields(&reply, 3)?;
+				Ok(count.parse()?)
+			}
+			["#CNT", _, _, ..] => {
+				self.fields(&reply, 3)?;
+				Err(self.unexpected(&reply))
+			}
+			["#ERR", ..] => Err(self.rejected(&reply, INDEX_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	async fn reset_counter(&self, line: u32) -> Result<()> {
+		check_input(line)?;
+
+		let reply = self.change(("$KE", "CNT", line, "RST"), "#CNT").await?;
+		match as_match!(reply) {
+			["#CNT", lid, "OK", ..] if lid.parse::<u32>()? == line => self.fields(&reply, 3),
+			["#CNT", _, _, ..] => {
+				self.fields(&reply, 3)?;
+				Err(self.unexpected(&reply))
+			}
+			["#ERR", ..] => Err(self.rejected(&reply, INDEX_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	async fn read_adc(&self, channel: u32) -> Result<u16> {
+		let reply = self.request(("$KE", "ADC", channel), "#ADC").await?;
+		match as_match!(reply) {
+			["#ADC", cid, value, ..] if cid.parse::<u32>()? == channel => {
+				self.fields(&reply, 3)?;
+				Ok(value.parse()?)
+			}
+			["#ADC", _, _, ..] => {
+				self.fields(&reply, 3)?;
+				Err(self.unexpected(&reply))
+			}
+			["#ERR", ..] => Err(self.rejected(&reply, INDEX_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	async fn board_temperature(&self) -> Result<f32> {
+		let reply = self.request(("$KE", "TMP"), "#TMP").await?;
+		match as_match!(reply) {
+			["#TMP", "ERR", ..] => {
+				self.fields(&reply, 2)?;
+				Err(Error::SensorFault)
+			}
+			["#TMP", celsius, ..] => {
+				self.fields(&reply, 2)?;
+				Ok(celsius.parse()?)
+			}
+			["#ERR", ..] => Err(self.rejected(&reply, NO_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	async fn relay_last_change(&self, relay: u32) -> Result<Duration> {
+		let reply = self.request(("$KE", "RLC", relay), "#RLC").await?;
+		match as_match!(reply) {
+			["#RLC", rid, secs, ..] if rid.parse::<u32>()? == relay => {
+				self.fields(&reply, 3)?;
+				Ok(Duration::from_secs(secs.parse()?))
+			}
+			["#RLC", _, _, ..] => {
+				self.fields(&reply, 3)?;
+				Err(self.unexpected(&reply))
+			}
+			["#ERR", ..] => Err(self.rejected(&reply, INDEX_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	async fn relay_cycle_count(&self, relay: u32) -> Result<u64> {
+		let reply = self.request(("$KE", "RCC", relay), "#RCC").await?;
+		match as_match!(reply) {
+			["#RCC", rid, count, ..] if rid.parse::<u32>()? == relay => {
+				self.fields(&reply, 3)?;
+				Ok(count.parse()?)
+			}
+			["#RCC", _, _, ..] => {
+				self.fields(&reply, 3)?;
+				Err(self.unexpected(&reply))
+			}
+			["#ERR", ..] => Err(self.rejected(&reply, INDEX_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	/// The module answers with `#RTM,ALL` followed by one field per relay: the time left in
+	/// tenths of a second, or `-` without a running timer.
+	async fn relay_timers(&self) -> Result<Vec<Option<Duration>>> {
+		let reply = self.request(("$KE", "RTM", "ALL"), "#RTM").await?;
+		match as_match!(reply) {
+			["#RTM", "ALL", ref timers @ ..] if timers.len() == RELAY_COUNT as usize => {
+				timers.iter().map(|timer| parse_timer(timer)).collect()
+			}
+			["#RTM", "ALL", ref timers @ ..] => Err(Error::InvalidPayload(format!(
+				"Expected {RELAY_COUNT} relay timers. Received: `{}`",
+				timers.len()
+			))),
+			["#ERR", ..] => Err(self.rejected(&reply, NO_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	/// Uses the native differential conversion when [`Capabilities::adc_differential`] is
+	/// set. Otherwise both channels are read back to back, so the result is skewed by one
+	/// round trip.
+	async fn adc_differential(&self, pos: u32, neg: u32) -> Result<i32> {
+		if !self.capabilities().adc_differential {
+			let pos = self.read_adc(pos).await?;
+			let neg = self.read_adc(neg).await?;
+			return Ok(i32::from(pos) - i32::from(neg));
+		}
+
+		let reply = self
+			.request(("$KE", "ADC", "DIF", pos, neg), "#ADC")
+			.await?;
+		match as_match!(reply) {
+			["#ADC", "DIF", value, ..] => {
+				self.fields(&reply, 3)?;
+				Ok(value.parse()?)
+			}
+			["#ERR", ..] => Err(self.rejected(&reply, ARGUMENT_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	async fn write_relays(&self, mask: u32, states: u32) -> Result<()> {
+		let pattern = relay_pattern(mask, states);
+		let reply = self.change(("$KE", "REL", "ALL", pattern), "#REL").await?;
+		match as_match!(reply) {
+			["#REL", "OK", ..] => self.fields(&reply, 2),
+			["#ERR", ..] => Err(self.rejected(&reply, ARGUMENT_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	async fn write_all_relays(&self, mask: u32) -> Result<()> {
+		let reply = self.change(("$KE", "WR", "ALL", mask), "#WR").await?;
+		match as_match!(reply) {
+			["#WR", "ALL", "OK", ..] => self.fields(&reply, 3),
+			["#ERR", ..] => Err(self.rejected(&reply, ARGUMENT_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	async fn set_output(&self, channel: u32, level: Signal) -> Result<()> {
+		let reply = self.change(("$KE", "OUT", channel, level), "#OUT").await?;
+		match as_match!(reply) {
+			["#OUT", "OK", ..] => self.fields(&reply, 2),
+			["#ERR", ..] => Err(self.rejected(&reply, INDEX_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	async fn output(&self, channel: u32) -> Result<Signal> {
+		let reply = self.request(("$KE", "OUT", channel), "#OUT").await?;
+		match as_match!(reply) {
+			["#OUT", cid, level, ..] if cid.parse::<u32>()? == channel => {
+				self.fields(&reply, 3)?;
+				Ok(level.parse()?)
+			}
+			["#OUT", _, _, ..] => {
+				self.fields(&reply, 3)?;
+				Err(self.unexpected(&reply))
+			}
+			["#ERR", ..] => Err(self.rejected(&reply, INDEX_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	/// Firmware without pulse support answers a bare `#ERR`, like to any command it doesn't
+	/// know, which is reported as [`Error::SyntaxError`].
+	async fn pulse_output(&self, channel: u32, count: u32, freq_hz: u32) -> Result<()> {
+		check_output(channel)?;
+		check_pulse(count, freq_hz)?;
+
+		let reply = self
+			.change(("$KE", "OUT", channel, "PLS", count, freq_hz), "#OUT")
+			.await?;
+		match as_match!(reply) {
+			["#OUT", "OK", ..] => self.fields(&reply, 2),
+			["#ERR", ..] => Err(self.rejected(&reply, PULSE_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	async fn link_relay_to_input(&self, relay: u32, line: u32, invert: bool) -> Result<()> {
+		check_relay(relay)?;
+		check_input(line)?;
+
+		let invert = u8::from(invert);
+		let reply = self
+			.change(("$KE", "LNK", relay, line, invert), "#LNK")
+			.await?;
+		match as_match!(reply) {
+			["#LNK", "OK", ..] => self.fields(&reply, 2),
+			["#ERR", ..] => Err(self.rejected(&reply, INDEX_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	async fn unlink_relay(&self, relay: u32) -> Result<()> {
+		check_relay(relay)?;
+
+		let reply = self.change(("$KE", "LNK", relay, "OFF"), "#LNK").await?;
+		match as_match!(reply) {
+			["#LNK", "OK", ..] => self.fields(&reply, 2),
+			["#ERR", ..] => Err(self.rejected(&reply, INDEX_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	async fn relay_link(&self, relay: u32) -> Result<Option<RelayLink>> {
+		check_relay(relay)?;
+
+		let reply = self.request(("$KE", "LNK", relay), "#LNK").await?;
+		match as_match!(reply) {
+			["#LNK", rid, "OFF", ..] if rid.parse::<u32>()? == relay => {
+				self.fields(&reply, 3)?;
+				Ok(None)
+			}
+			["#LNK", rid, line, invert, ..] if rid.parse::<u32>()? == relay => {
+				self.fields(&reply, 4)?;
+				Ok(Some(RelayLink {
+					line: line.parse()?,
+					invert: invert == "1",
+				}))
+			}
+			["#LNK", ..] => Err(self.unexpected(&reply)),
+			["#ERR", ..] => Err(self.rejected(&reply, INDEX_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	/// Event settings are the kinds turned on through this gateway, which the module applies
+	/// per connection.
+	async fn running_config(&self) -> Result<BoardConfig> {
+		let mut cfg = read_outputs(self).await?;
+		let events = self.session.lock().unwrap().events.clone();
+		cfg.events = events.into_iter().map(|kind| (kind, true)).collect();
+		Ok(cfg)
+	}
+
+	async fn set_command_watchdog(&self, window: Option<Duration>) -> Result<()> {
+		let keepalive = *self.keepalive.lock().unwrap();
+		if let (Some(interval), Some(window)) = (keepalive, window) {
+			check_keepalive(interval, window)?;
+		}
+
+		let reply = match window {
+			Some(window) if window.as_secs() == 0 || window.subsec_nanos() != 0 => {
+				return Err(Error::InvalidPayload(format!(
+					"The watchdog window must be a whole number of seconds, at least one. Received: `{window:?}`"
+				)))
+			}
+			Some(window) => {
+				self
+					.change(("$KE", "WDT", window.as_secs()), "#WDT")
+					.await?
+			}
+			None => self.change(("$KE", "WDT", "OFF"), "#WDT").await?,
+		};
+
+		match as_match!(reply) {
+			["#WDT", "OK", ..] => self.fields(&reply, 2)?,
+			["#ERR", ..] => return Err(self.rejected(&reply, ARGUMENT_CODES)),
+			_ => return Err(self.unknown(&reply)),
+		}
+
+		*self.watchdog.lock().unwrap() = window;
+		Ok(())
+	}
+
+	async fn save_settings(&self) -> Result<()> {
+		let reply = self.change(("$KE", "SAVE"), "#SAVE").await?;
+		match as_match!(reply) {
+			["#SAVE", "OK", ..] => self.fields(&reply, 2),
+			["#ERR", ..] => Err(self.rejected(&reply, NO_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	async fn set_pwm(&self, channel: u32, duty: u8) -> Result<()> {
+		check_pwm(channel)?;
+		if duty > PWM_DUTY_MAX {
+			return Err(Error::InvalidPayload(format!(
+				"The PWM duty must be between 0 and {PWM_DUTY_MAX}. Received: `{duty}`"
+			)));
+		}
+
+		let reply = self.change(("$KE", "PWM", channel, duty), "#PWM").await?;
+		match as_match!(reply) {
+			["#PWM", "OK", ..] => self.fields(&reply, 2),
+			["#ERR", ..] => Err(self.rejected(&reply, ARGUMENT_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	async fn reboot(&self) -> Result<()> {
+		let reply = match self.change(("$KE", "RST"), "#RST").await {
+			Ok(reply) => reply,
+			Err(Error::Closed | Error::Send) => return Ok(()),
+			Err(err) => return Err(err),
+		};
+		match as_match!(reply) {
+			["#RST", "OK", ..] => self.fields(&reply, 2),
+			["#ERR", ..] => Err(self.rejected(&reply, NO_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	/// The module answers with `#CFG,<setting>,...` lines followed by `#CFG,END`.
+	async fn export_config(&self) -> Result<String> {
+		let frames = self
+			.exchange_multi(("$KE", "CFG", "ALL"), |parts| {
+				matches!(as_match!(parts), ["#CFG", "END"] | ["#ERR", ..])
+			})
+			.await?;
+
+		let mut lines = Vec::new();
+		for frame in frames {
+			match as_match!(frame) {
+				["#CFG", "END", ..] => self.fields(&frame, 2)?,
+				["#CFG", ref setting @ ..] => lines.push(setting.join(",")),
+				["#ERR", ..] => return Err(self.rejected(&frame, NO_CODES)),
+				_ => return Err(self.unknown(&frame)),
+			}
+		}
+
+		Ok(lines.join("\n"))
+	}
+
+	/// Each line is sent as `$KE,CFG,SET,<setting>`, then `$KE,CFG,APPLY` makes the module
+	/// take them over.
+	async fn import_config(&self, blob: &str) -> Result<()> {
+		for line in blob.lines().map(str::trim).filter(|line| !line.is_empty()) {
+			let cmd = Command::builder()
+				.push("CFG")
+				.push("SET")
+				.extend(line.split(','))
+				.build();
+			let reply = self.change(cmd, "#CFG").await?;
+			match as_match!(reply) {
+				["#CFG", "OK", ..] => self.fields(&reply, 2)?,
+				["#ERR"] => {
+					return Err(Error::InvalidPayload(format!(
+						"The module rejected the setting `{line}`"
+					)))
+				}
+				["#ERR", ..] => return Err(self.rejected(&reply, ARGUMENT_CODES)),
+				_ => return Err(self.unknown(&reply)),
+			}
+		}
+
+		let reply = match self.change(("$KE", "CFG", "APPLY"), "#CFG").await {
+			Ok(reply) => reply,
+			// The module went down to reboot before answering.
+			Err(Error::Closed | Error::Send) => return Ok(()),
+			Err(err) => return Err(err),
+		};
+		match as_match!(reply) {
+			["#CFG", "OK", ..] => self.fields(&reply, 2),
+			["#ERR", ..] => Err(self.rejected(&reply, NO_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	/// The module answers `#1WT,<sensor>,ERR` if the sensor doesn't respond.
+	async fn read_temperature(&self, sensor: u32) -> Result<f32> {
+		let reply = self.request(("$KE", "1WT", sensor), "#1WT").await?;
+		match as_match!(reply) {
+			["#1WT", sid, "ERR", ..] if sid.parse::<u32>()? == sensor => {
+				self.fields(&reply, 3)?;
+				Err(Error::SensorFault)
+			}
+			["#1WT", sid, celsius, ..] if sid.parse::<u32>()? == sensor => {
+				self.fields(&reply, 3)?;
+				Ok(celsius.parse()?)
+			}
+			["#1WT", _, _, ..] => {
+				self.fields(&reply, 3)?;
+				Err(self.unexpected(&reply))
+			}
+			["#ERR", ..] => Err(self.rejected(&reply, INDEX_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	async fn temperature_extremes(&self, sensor: u32) -> Result<(f32, f32)> {
+		let reply = self.request(("$KE", "1WT", sensor, "EXT"), "#1WT").await?;
+		match as_match!(reply) {
+			["#1WT", sid, min, max, ..] if sid.parse::<u32>()? == sensor => {
+				self.fields(&reply, 4)?;
+				Ok((min.parse()?, max.parse()?))
+			}
+			["#1WT", _, _, _, ..] => {
+				self.fields(&reply, 4)?;
+				Err(self.unexpected(&reply))
+			}
+			["#ERR", ..] => Err(self.rejected(&reply, INDEX_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	async fn reset_temperature_extremes(&self, sensor: u32) -> Result<()> {
+		let reply = self
+			.change(("$KE", "1WT", sensor, "EXT", "RST"), "#1WT")
+			.await?;
+		match as_match!(reply) {
+			["#1WT", "OK", ..] => self.fields(&reply, 2),
+			["#ERR", ..] => Err(self.rejected(&reply, INDEX_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	/// The module answers with `#HELP,<command>,...` lines followed by `#HELP,END`.
+	async fn supported_commands(&self) -> Result<Vec<String>> {
+		let frames = self
+			.exchange_multi(("$KE", "HELP"), |parts| {
+				matches!(as_match!(parts), ["#HELP", "END"] | ["#ERR", ..])
+			})
+			.await?;
+
+		let mut commands = Vec::new();
+		for frame in frames {
+			match as_match!(frame) {
+				["#HELP", "END", ..] => self.fields(&frame, 2)?,
+				["#HELP", ..] => commands.extend(frame.into_iter().skip(1)),
+				["#ERR", ..] => return Err(self.rejected(&frame, NO_CODES)),
+				_ => return Err(self.unknown(&frame)),
+			}
+		}
+
+		Ok(commands)
+	}
+
+	async fn firmware_details(&self) -> Result<FirmwareInfo> {
+		let reply = self.request(("$KE", "INF"), "#INF").await?;
+		match as_match!(reply) {
+			["#INF", _, ..] => Ok(FirmwareInfo::from_parts(&reply[1..])),
+			["#ERR", ..] => Err(self.rejected(&reply, NO_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	async fn event_format_version(&self) -> Result<u32> {
+		let reply = self.request(("$KE", "MSG", "VER"), "#MSG").await?;
+		match as_match!(reply) {
+			["#MSG", "VER", version, ..] => {
+				self.fields(&reply, 3)?;
+				Ok(version.parse()?)
+			}
+			["#ERR", ..] => Err(self.rejected(&reply, NO_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	async fn advanced_input(&self, line: u32) -> Result<AdvancedData> {
+		let reply = self.request(("$KE", "ADVC", line), "#ADVC").await?;
+		match as_match!(reply) {
+			["#ADVC", lid, bits, raw, ..] if lid.parse::<u32>()? == line => {
+				self.fields(&reply, 4)?;
+				AdvancedData::parse(bits, raw)
+			}
+			["#ADVC", _, _, _, ..] => {
+				self.fields(&reply, 4)?;
+				Err(self.unexpected(&reply))
+			}
+			["#ERR", ..] => Err(self.rejected(&reply, INDEX_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	/// The module answers with one `#1WT,ROM,<index>,<rom>` line per sensor, followed by
+	/// `#1WT,SCAN,OK`.
+	async fn scan_onewire(&self) -> Result<Vec<OneWireSensor>> {
+		let frames = self
+			.exchange_multi(("$KE", "1WT", "SCAN"), |parts| {
+				matches!(as_match!(parts), ["#1WT", "SCAN", "OK"] | ["#ERR", ..])
+			})
+			.await?;
+
+		let mut sensors = Vec::new();
+		for frame in frames {
+			match as_match!(frame) {
+				["#1WT", "ROM", index, rom, ..] => {
+					self.fields(&frame, 4)?;
+					sensors.push(OneWireSensor {
+						index: index.parse()?,
+						rom: u64::from_str_radix(rom, 16)?,
+					});
+				}
+				["#1WT", "SCAN", "OK", ..] => self.fields(&frame, 3)?,
+				["#ERR", ..] => return Err(self.rejected(&frame, NO_CODES)),
+				_ => return Err(self.unknown(&frame)),
+			}
+		}
+
+		Ok(sensors)
+	}
+
+	/// The module answers with `#ADC,ALL` followed by one value per channel.
+	async fn adc_all(&self) -> Result<Vec<u16>> {
+		let reply = self.request(("$KE", "ADC", "ALL"), "#ADC").await?;
+		match as_match!(reply) {
+			["#ADC", "ALL", ref values @ ..] if values.len() == ADC_COUNT as usize => {
+				values.iter().map(|value| Ok(value.parse()?)).collect()
+			}
+			["#ADC", "ALL", ref values @ ..] => Err(Error::InvalidPayload(format!(
+				"Expected {ADC_COUNT} ADC values. Received: `{}`",
+				values.len()
+			))),
+			["#ERR", ..] => Err(self.rejected(&reply, NO_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	/// The module answers with `#RD,ALL` followed by one level per line, so the length of
+	/// the result follows the module's input count.
+	async fn read_all_inputs(&self) -> Result<Vec<Signal>> {
+		let reply = self.request(("$KE", "RD", "ALL"), "#RD").await?;
+		match as_match!(reply) {
+			["#RD", "ALL", ref levels @ ..] => levels.iter().map(|level| level.parse()).collect(),
+			["#ERR", ..] => Err(self.rejected(&reply, NO_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+
+	/// The module answers `#ST,<relays>,<inputs>,<adc>...`, where relays and inputs are
+	/// written as one digit per line, line 1 first.
+	async fn status_snapshot(&self) -> Result<BoardSnapshot> {
+		let reply = self.request(("$KE", "ST"), "#ST").await?;
+		match as_match!(reply) {
+			["#ST", relays, inputs, ref adc @ ..] => Ok(BoardSnapshot {
+				relays: relays.chars().map(|on| on == '1').collect(),
+				inputs: inputs
+					.chars()
+					.map(|level| level.to_string().parse())
+					.collect::<Result<_>>()?,
+				adc: adc
+					.iter()
+					.map(|value| Ok(value.parse()?))
+					.collect::<Result<_>>()?,
+			}),
+			["#ERR", ..] => Err(self.rejected(&reply, NO_CODES)),
+			_ => Err(self.unknown(&reply)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::scripted;
+	use crate::testing::scripted_with;
+	use crate::FakeDevice;
+	use crate::Signal;
+	use crate::OUTPUT_COUNT;
+	use crate::PULSE_MAX_FREQ_HZ;
+	use crate::PWM_COUNT;
+	use std::sync::atomic::AtomicBool;
+	use std::sync::atomic::Ordering;
+	use tokio::io::AsyncBufReadExt;
+	use tokio::io::AsyncReadExt;
+	use tokio::io::AsyncWriteExt;
+	use tokio::io::BufReader;
+	use tokio::net::TcpListener;
+	use tokio::net::TcpStream;
+	use tokio::sync::broadcast::error::RecvError;
+
+	#[tokio::test]
+	async fn gateway_ping() -> Result<()> {
+		let listener = TcpListener::bind("0.0.0.0:0").await?;
+		let addr = listener.local_addr()?;
+
+		let gw = StreamGateway::connect(TcpStream::connect(addr).await?);
+
+		let (mut stream, _) = listener.accept().await.unwrap();
+
+		tokio::spawn(async move {
+			let mut buf = vec![0; 64];
+			let _ = stream.read(&mut buf).await.unwrap();
+			stream.write_all(b"#OK\r\n").await.unwrap();
+		});
+
+		gw.ping().await?;
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_events() -> Result<()> {
+		let listener = TcpListener::bind("0.0.0.0:0").await?;
+		let addr = listener.local_addr()?;
+
+		let gw = StreamGateway::connect(TcpStream::connect(addr).await?);
+
+		let (mut stream, _) = listener.accept().await.unwrap();
+
+		tokio::spawn(async move {
+			stream.write_all(b"#M,EIN,1,1\r\n").await.unwrap();
+			stream.write_all(b"#M,EIN,1,0\r\n").await.unwrap();
+			stream.write_all(b"#M,EIN,2,1\r\n").await.unwrap();
+			stream.write_all(b"#M,EIN,2,0\r\n").await.unwrap();
+		});
+
+		let mut sub = gw.subscibe();
+
+		let event = sub.recv().await.unwrap();
+		assert_eq!(
+			event,
+			Event::Ein {
+				line: 1,
+				signal: Signal::High,
+			}
+		);
+		let event = sub.recv().await.unwrap();
+		assert_eq!(
+			event,
+			Event::Ein {
+				line: 1,
+				signal: Signal::Low,
+			}
+		);
+		let event = sub.recv().await.unwrap();
+		assert_eq!(
+			event,
+			Event::Ein {
+				line: 2,
+				signal: Signal::High,
+			}
+		);
+		let event = sub.recv().await.unwrap();
+		assert_eq!(
+			event,
+			Event::Ein {
+				line: 2,
+				signal: Signal::Low,
+			}
+		);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_event_without_subscribers() -> Result<()> {
+		let (client, device) = tokio::io::duplex(1024);
+		let mut gw = StreamGateway::connect(client);
+		// The gateway keeps a receiver of its own to subscribe from. Swapping it for one of
+		// an unrelated channel leaves the reader's sender without any receivers.
+		gw.events = broadcast::channel(1).1;
+
+		let (rd, mut wr) = tokio::io::split(device);
+		wr.write_all(b"#M,EIN,1,1\r\n").await?;
+		let mut lines = BufReader::new(rd).lines();
+		let ping = tokio::spawn(async move { gw.ping().await });
+		assert_eq!(lines.next_line().await?.as_deref(), Some("$KE"));
+		wr.write_all(b"#OK\r\n").await?;
+		ping.await.unwrap()?;
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_read_temperature() -> Result<()> {
+		let (gw, received) = scripted(|cmd| match cmd {
+			"$KE,1WT,0" => "#1WT,0,23.5".into(),
+			"$KE,1WT,1" => "#1WT,1,-5.2".into(),
+			"$KE,1WT,2" => "#1WT,2,ERR".into(),
+			_ => "#ERR".into(),
+		});
+
+		assert_eq!(gw.read_temperature(0).await?, 23.5);
+		assert_eq!(gw.read_temperature(1).await?, -5.2);
+		assert!(matches!(
+			gw.read_temperature(2).await,
+			Err(Error::SensorFault)
+		));
+		assert_eq!(received.lock().unwrap()[0], "$KE,1WT,0");
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_wt1_event() -> Result<()> {
+		let (client, mut device) = tokio::io::duplex(1024);
+		let gw = StreamGateway::connect(client);
+		let mut sub = gw.subscibe();
+
+		device.write_all(b"#M,1WT,3,-5.2\r\n").await?;
+		assert_eq!(
+			sub.recv().await.unwrap(),
+			Event::Wt1 {
+				sensor: 3,
+				celsius: -5.2,
+			}
+		);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_unknown_event() -> Result<()> {
+		let (client, mut device) = tokio::io::duplex(1024);
+		let gw = StreamGateway::connect(client);
+		let mut sub = gw.subscibe();
+
+		device.write_all(b"#M,FOO,1,2,3\r\n").await?;
+		assert_eq!(
+			sub.recv().await.unwrap(),
+			Event::Unknown(vec!["FOO".into(), "1".into(), "2".into(), "3".into()])
+		);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_rele_event() -> Result<()> {
+		let (client, mut device) = tokio::io::duplex(1024);
+		let gw = StreamGateway::connect(client);
+		let mut sub = gw.subscibe();
+
+		device.write_all(b"#M,RELE,2,1\r\n").await?;
+		assert_eq!(
+			sub.recv().await.unwrap(),
+			Event::Rele {
+				relay: 2,
+				state: true,
+			}
+		);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_custom_event() -> Result<()> {
+		#[derive(Debug, PartialEq)]
+		struct Counter(u32);
+
+		let (client, mut device) = tokio::io::duplex(1024);
+		let gw = StreamGateway::connect(client);
+		gw.register_event_parser("MYKIND", |fields| match fields {
+			[count] => Ok(Counter(count.parse()?)),
+			_ => Err(Error::UnknownMessage(fields.to_vec())),
+		});
+		let mut sub = gw.subscibe();
+
+		device
+			.write_all(b"#M,MYKIND,x\r\n#M,MYKIND,7\r\n#M,OTHER,1\r\n#M,RELE,1,0\r\n")
+			.await?;
+
+		let Event::Custom(event) = sub.recv().await.unwrap() else {
+			panic!("Expected a custom event");
+		};
+		assert_eq!(event.kind(), "MYKIND");
+		assert_eq!(event.fields(), ["7"]);
+		assert_eq!(event.downcast_ref::<Counter>(), Some(&Counter(7)));
+		assert_eq!(
+			sub.recv().await.unwrap(),
+			Event::Unknown(vec!["OTHER".into(), "1".into()])
+		);
+		assert_eq!(
+			sub.recv().await.unwrap(),
+			Event::Rele {
+				relay: 1,
+				state: false,
+			}
+		);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_detached_relays() -> Result<()> {
+		let (gw, received) = scripted(|cmd| match cmd {
+			"$KE,REL,9,1" => "#ERR,IDX".into(),
+			_ => "#REL,OK".into(),
+		});
+		let (errors, mut failed) = mpsc::unbounded_channel();
+		let relays = gw.detached_relays(move |err| errors.send(err).unwrap());
+
+		for i in 0..100 {
+			let action = if i % 2 == 0 {
+				RelayAction::On
+			} else {
+				RelayAction::Off
+			};
+			relays.relay(i % RELAY_COUNT + 1, action, None)?;
+		}
+		relays.relay(9, RelayAction::On, None)?;
+
+		assert!(matches!(
+			failed.recv().await,
+			Some(Error::Rejected(Rejection::InvalidIndex))
+		));
+		let received = received.lock().unwrap();
+		assert_eq!(received.len(), 101);
+		assert_eq!(received[0], "$KE,REL,1,1");
+		assert_eq!(received[99], "$KE,REL,4,0");
+		assert!(failed.try_recv().is_err());
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_relay() -> Result<()> {
+		let listener = TcpListener::bind("0.0.0.0:0").await?;
+		let addr = listener.local_addr()?;
+
+		let gw = StreamGateway::connect(TcpStream::connect(addr).await?);
+
+		let (mut stream, _) = listener.accept().await.unwrap();
+
+		tokio::spawn(async move {
+			let mut buf = vec![0; 64];
+			let _ = stream.read(&mut buf).await.unwrap();
+			stream.write_all(b"#REL,OK\r\n").await.unwrap();
+		});
+
+		gw.relay(1, RelayAction::On, None).await?;
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_status() -> Result<()> {
+		let listener = TcpListener::bind("0.0.0.0:0").await?;
+		let addr = listener.local_addr()?;
+
+		let gw = StreamGateway::connect(TcpStream::connect(addr).await?);
+
+		let (mut stream, _) = listener.accept().await.unwrap();
+
+		tokio::spawn(async move {
+			let mut buf = vec![0; 64];
+			let _ = stream.read(&mut buf).await.unwrap();
+			stream.write_all(b"#RDR,3,1\r\n").await.unwrap();
+		});
+
+		assert!(gw.relay_status(3).await?);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_line_signal() -> Result<()> {
+		let listener = TcpListener::bind("0.0.0.0:0").await?;
+		let addr = listener.local_addr()?;
+
+		let gw = StreamGateway::connect(TcpStream::connect(addr).await?);
+
+		let (mut stream, _) = listener.accept().await.unwrap();
+
+		tokio::spawn(async move {
+			let mut buf = vec![0; 64];
+			let _ = stream.read(&mut buf).await.unwrap();
+			stream.write_all(b"#RD,3,1\r\n").await.unwrap();
+		});
+
+		assert!(gw.line_signal(3).await?.is_high());
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_read_adc() -> Result<()> {
+		let listener = TcpListener::bind("0.0.0.0:0").await?;
+		let addr = listener.local_addr()?;
+
+		let gw = StreamGateway::connect(TcpStream::connect(addr).await?);
+
+		let (mut stream, _) = listener.accept().await.unwrap();
+
+		tokio::spawn(async move {
+			let mut buf = vec![0; 64];
+			let _ = stream.read(&mut buf).await.unwrap();
+			stream.write_all(b"#ADC,1,2048\r\n").await.unwrap();
+		});
+
+		assert_eq!(gw.read_adc(1).await?, 2048);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_read_all_relays() -> Result<()> {
+		let listener = TcpListener::bind("0.0.0.0:0").await?;
+		let addr = listener.local_addr()?;
+
+		let gw = StreamGateway::connect(TcpStream::connect(addr).await?);
+
+		let (mut stream, _) = listener.accept().await.unwrap();
+
+		let device = tokio::spawn(async move {
+			let mut buf = vec![0; 64];
+			let len = stream.read(&mut buf).await.unwrap();
+			stream.write_all(b"#RDR,ALL,1,0,1\r\n").await.unwrap();
+			buf.truncate(len);
+			buf
+		});
+
+		assert_eq!(gw.read_all_relays().await?, vec![true, false, true]);
+		assert_eq!(device.await.unwrap(), b"$KE,RDR,ALL\r\n");
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_read_all_inputs() -> Result<()> {
+		let (gw, received) = scripted(|cmd| match cmd {
+			"$KE,RD,ALL" => "#RD,ALL,1,0,1,0".into(),
+			_ => "#ERR".into(),
+		});
+
+		assert_eq!(
+			gw.read_all_inputs().await?,
+			vec![Signal::High, Signal::Low, Signal::High, Signal::Low]
+		);
+		assert_eq!(*received.lock().unwrap(), vec!["$KE,RD,ALL"]);
+
+		let (gw, _) = scripted(|_| "#RD,ALL,1,?,0".into());
+		assert!(matches!(
+			gw.read_all_inputs().await,
+			Err(Error::InvalidPayload(_))
+		));
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_unexpected_message() -> Result<()> {
+		let listener = TcpListener::bind("0.0.0.0:0").await?;
+		let addr = listener.local_addr()?;
+
+		let gw = StreamGateway::connect(TcpStream::connect(addr).await?);
+
+		let (mut stream, _) = listener.accept().await.unwrap();
+
+		tokio::spawn(async move {
+			let mut buf = vec![0; 64];
+			let _ = stream.read(&mut buf).await.unwrap();
+			stream.write_all(b"#RDR,3,1\r\n").await.unwrap();
+		});
+
+		assert!(matches!(
+			gw.relay_status(1).await,
+			Err(Error::UnexpectedMessage(_))
+		));
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_unexpected_header() -> Result<()> {
+		let (gw, _) = scripted(|cmd| match cmd {
+			"$KE,RDR,1" => "#RD,1,1".into(),
+			_ => "#ERR".into(),
+		});
+
+		match gw.relay_status(1).await {
+			Err(Error::UnexpectedHeader { expected, got }) => {
+				assert_eq!((expected, got.as_str()), ("#RDR", "#RD"))
+			}
+			res => panic!("Unexpected result: {res:?}"),
+		}
+		assert!(matches!(
+			gw.relay_status(2).await,
+			Err(Error::SyntaxError(_))
+		));
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_drops_duplicate_reply() -> Result<()> {
+		let listener = TcpListener::bind("0.0.0.0:0").await?;
+		let addr = listener.local_addr()?;
+
+		let gw = StreamGateway::connect(TcpStream::connect(addr).await?);
+
+		let (mut stream, _) = listener.accept().await.unwrap();
+
+		tokio::spawn(async move {
+			let mut buf = vec![0; 64];
+			let _ = stream.read(&mut buf).await.unwrap();
+			// The delayed original reply followed by the reply to the retry.
+			stream.write_all(b"#RDR,1,1\r\n#RDR,1,1\r\n").await.unwrap();
+			let _ = stream.read(&mut buf).await.unwrap();
+			stream.write_all(b"#RDR,2,0\r\n").await.unwrap();
+		});
+
+		assert!(gw.relay_status(1).await?);
+		assert!(!gw.relay_status(2).await?);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_close() -> Result<()> {
+		let listener = TcpListener::bind("0.0.0.0:0").await?;
+		let addr = listener.local_addr()?;
+
+		let gw = StreamGateway::connect(TcpStream::connect(addr).await?);
+
+		let (mut stream, _) = listener.accept().await.unwrap();
+
+		let device = tokio::spawn(async move {
+			let mut buf = vec![0; 64];
+			let n = stream.read(&mut buf).await.unwrap();
+			stream.write_all(b"#OK\r\n").await.unwrap();
+			let eof = stream.read(&mut buf).await.unwrap();
+			(buf[..n].to_vec(), eof)
+		});
+
+		gw.close().await?;
+		assert!(gw.is_closed());
+		assert!(gw.ping().await.is_err());
+
+		let (cmd, eof) = device.await.unwrap();
+		assert_eq!(cmd, b"$KE,QUIT\r\n");
+		assert_eq!(eof, 0);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_close_silent_device() -> Result<()> {
+		tokio::time::pause();
+		let (gw, received) = scripted(|_| String::new());
+
+		let started = tokio::time::Instant::now();
+		assert!(matches!(gw.close().await, Err(Error::Timeout)));
+		assert!(started.elapsed() >= LOGOUT_TIMEOUT);
+		assert!(started.elapsed() < LOGOUT_TIMEOUT + Duration::from_secs(1));
+		assert!(gw.is_closed());
+		assert!(gw.ping().await.is_err());
+		assert_eq!(*received.lock().unwrap(), ["$KE,QUIT"]);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_raw_command_multi() -> Result<()> {
+		let listener = TcpListener::bind("0.0.0.0:0").await?;
+		let addr = listener.local_addr()?;
+
+		let gw = StreamGateway::connect(TcpStream::connect(addr).await?);
+
+		let (mut stream, _) = listener.accept().await.unwrap();
+
+		tokio::spawn(async move {
+			let mut buf = vec![0; 64];
+			let _ = stream.read(&mut buf).await.unwrap();
+			stream
+				.write_all(b"#CFG,IP,192.168.0.101\r\n")
+				.await
+				.unwrap();
+			stream
+				.write_all(b"#CFG,MASK,255.255.255.0\r\n")
+				.await
+				.unwrap();
+			stream.write_all(b"#CFG,PORT,2424\r\n").await.unwrap();
+			stream.write_all(b"#CFG,END\r\n").await.unwrap();
+			let _ = stream.read(&mut buf).await.unwrap();
+			stream.write_all(b"#OK\r\n").await.unwrap();
+		});
+
+		let frames = gw
+			.raw_command_multi(vec!["CFG".into(), "ALL".into()], |parts| {
+				parts == ["#CFG", "END"]
+			})
+			.await?;
+
+		assert_eq!(
+			frames,
+			vec![
+				vec!["#CFG", "IP", "192.168.0.101"],
+				vec!["#CFG", "MASK", "255.255.255.0"],
+				vec!["#CFG", "PORT", "2424"],
+				vec!["#CFG", "END"],
+			]
+		);
+
+		gw.ping().await?;
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_board_temperature() -> Result<()> {
+		let listener = TcpListener::bind("0.0.0.0:0").await?;
+		let addr = listener.local_addr()?;
+
+		let gw = StreamGateway::connect(TcpStream::connect(addr).await?);
+
+		let (mut stream, _) = listener.accept().await.unwrap();
+
+		tokio::spawn(async move {
+			let mut buf = vec![0; 64];
+			let _ = stream.read(&mut buf).await.unwrap();
+			stream.write_all(b"#TMP,31.5\r\n").await.unwrap();
+			let _ = stream.read(&mut buf).await.unwrap();
+			stream.write_all(b"#TMP,ERR\r\n").await.unwrap();
+		});
+
+		assert_eq!(gw.board_temperature().await?, 31.5);
+		assert!(matches!(
+			gw.board_temperature().await,
+			Err(Error::SensorFault)
+		));
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_open() -> Result<()> {
+		let listener = TcpListener::bind("0.0.0.0:0").await?;
+		let addr = listener.local_addr()?;
+
+		let device = tokio::spawn(async move {
+			let (stream, _) = listener.accept().await.unwrap();
+			let (rd, mut wr) = stream.into_split();
+			let mut lines = BufReader::new(rd).lines();
+			let mut received = Vec::new();
+
+			while let Some(line) = lines.next_line().await.unwrap() {
+				let reply: &[u8] = match line.as_str() {
+					"$KE,PSW,SET,secret" => b"#PSW,SET,OK\r\n",
+					"$KE,MSG,S,EIN,SET,ON" => b"#MSG,SET,OK\r\n",
+					_ => b"#ERR\r\n",
+				};
+				wr.write_all(reply).await.unwrap();
+				received.push(line);
+			}
+
+			received
+		});
+
+		let opts = GatewayOptions::new()
+			.with_password("secret")
+			.with_event(EventKind::Ein)
+			.with_connect_timeout(Duration::from_secs(1));
+		let gw = StreamGateway::open(addr, opts).await?;
+		drop(gw);
+
+		assert_eq!(
+			device.await.unwrap(),
+			["$KE,PSW,SET,secret", "$KE,MSG,S,EIN,SET,ON"]
+		);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_keepalive() -> Result<()> {
+		let (client, device) = tokio::io::duplex(1024);
+		let gw = StreamGateway::connect(client);
+
+		tokio::spawn(async move {
+			let (rd, mut wr) = tokio::io::split(device);
+			let mut lines = BufReader::new(rd).lines();
+			// Answers the first ping, then goes quiet.
+			let _ = lines.next_line().await;
+			wr.write_all(b"#OK\r\n").await.unwrap();
+			while let Ok(Some(_)) = lines.next_line().await {}
+		});
+
+		let interval = Duration::from_millis(20);
+		let keepalive = gw.spawn_keepalive(interval)?;
+		let mut failures = keepalive.failures();
+
+		let failed = failures.wait_for(|&failures| failures >= 1);
+		tokio::time::timeout(interval * 5, failed)
+			.await
+			.expect("the keepalive should report the unanswered ping")
+			.unwrap();
+
+		drop(keepalive);
+		assert!(failures.changed().await.is_err());
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_keepalive_watchdog() -> Result<()> {
+		let (gw, received) = scripted(|cmd| match cmd {
+			"$KE" => "#OK".into(),
+			cmd if cmd.starts_with("$KE,WDT,") => "#WDT,OK".into(),
+			_ => "#ERR".into(),
+		});
+
+		gw.set_command_watchdog(Some(Duration::from_secs(5)))
+			.await?;
+		assert!(matches!(
+			gw.spawn_keepalive(Duration::from_secs(5)),
+			Err(Error::InvalidPayload(_))
+		));
+
+		let keepalive = gw.spawn_keepalive(Duration::from_secs(2))?;
+		assert!(matches!(
+			gw.set_command_watchdog(Some(Duration::from_secs(2))).await,
+			Err(Error::InvalidPayload(_))
+		));
+		assert_eq!(gw.command_watchdog(), Some(Duration::from_secs(5)));
+
+		// Once the keepalive is gone, nothing is left to check against.
+		drop(keepalive);
+		gw.set_command_watchdog(Some(Duration::from_secs(2)))
+			.await?;
+		assert_eq!(*received.lock().unwrap(), ["$KE,WDT,5", "$KE,WDT,2"]);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_ping_rtt() -> Result<()> {
+		let (client, device) = tokio::io::duplex(1024);
+		let gw = StreamGateway::connect(client);
+
+		tokio::spawn(async move {
+			let (rd, mut wr) = tokio::io::split(device);
+			let mut lines = BufReader::new(rd).lines();
+			while let Ok(Some(_)) = lines.next_line().await {
+				tokio::time::sleep(Duration::from_millis(20)).await;
+				wr.write_all(b"#OK\r\n").await.unwrap();
+			}
+		});
+
+		let rtt = gw.ping_rtt().await?;
+		assert!(rtt >= Duration::from_millis(20));
+		assert!(rtt < Duration::from_secs(1));
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_open_stream() -> Result<()> {
+		let (client, device) = tokio::io::duplex(1024);
+
+		let device = tokio::spawn(async move {
+			let (rd, mut wr) = tokio::io::split(device);
+			let mut lines = BufReader::new(rd).lines();
+			let mut received = Vec::new();
+
+			while let Some(line) = lines.next_line().await.unwrap() {
+				let reply: &[u8] = match line.as_str() {
+					"$KE,PSW,SET,secret" => b"#PSW,SET,OK\r\n",
+					"$KE,MSG,S,EIN,SET,ON" | "$KE,MSG,S,ADVC,SET,ON" => b"#MSG,SET,OK\r\n",
+					_ => b"#ERR\r\n",
+				};
+				wr.write_all(reply).await.unwrap();
+				received.push(line);
+			}
+
+			received
+		});
+
+		let opts = GatewayOptions::new()
+			.with_password("secret")
+			.with_event(EventKind::Ein)
+			.with_event(EventKind::Advc)
+			.with_command_timeout(Duration::from_secs(1));
+		let gw = StreamGateway::open_stream(client, opts).await?;
+		drop(gw);
+
+		assert_eq!(
+			device.await.unwrap(),
+			[
+				"$KE,PSW,SET,secret",
+				"$KE,MSG,S,EIN,SET,ON",
+				"$KE,MSG,S,ADVC,SET,ON"
+			]
+		);
+
+		let (client, device) = tokio::io::duplex(1024);
+		tokio::spawn(async move {
+			let (rd, mut wr) = tokio::io::split(device);
+			let mut lines = BufReader::new(rd).lines();
+			while let Ok(Some(_)) = lines.next_line().await {
+				wr.write_all(b"#ERR,PSW\r\n").await.unwrap();
+			}
+		});
+
+		let opts = GatewayOptions::new().with_password("wrong");
+		assert!(StreamGateway::open_stream(client, opts).await.is_err());
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_relay_last_change() -> Result<()> {
+		let listener = TcpListener::bind("0.0.0.0:0").await?;
+		let addr = listener.local_addr()?;
+
+		let gw = StreamGateway::connect(TcpStream::connect(addr).await?);
+
+		let (mut stream, _) = listener.accept().await.unwrap();
+
+		tokio::spawn(async move {
+			let mut buf = vec![0; 64];
+			let _ = stream.read(&mut buf).await.unwrap();
+			stream.write_all(b"#RLC,2,95\r\n").await.unwrap();
+		});
+
+		assert_eq!(gw.relay_last_change(2).await?, Duration::from_secs(95));
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_connect_with_label() -> Result<()> {
+		let listener = TcpListener::bind("0.0.0.0:0").await?;
+		let addr = listener.local_addr()?;
+
+		let opts = GatewayOptions::new().with_label("gate-1");
+		let gw = StreamGateway::connect_with(TcpStream::connect(addr).await?, &opts)?;
+
+		let (mut stream, _) = listener.accept().await.unwrap();
+
+		tokio::spawn(async move {
+			let mut buf = vec![0; 64];
+			let _ = stream.read(&mut buf).await.unwrap();
+			stream.write_all(b"#OK\r\n").await.unwrap();
+		});
+
+		gw.ping().await?;
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_adc_differential_fallback() -> Result<()> {
+		let (gw, received) = scripted(|cmd| match cmd {
+			"$KE,ADC,1" => "#ADC,1,1000".into(),
+			"$KE,ADC,2" => "#ADC,2,2500".into(),
+			_ => "#ERR".into(),
+		});
+
+		assert_eq!(gw.adc_differential(1, 2).await?, -1500);
+		assert_eq!(*received.lock().unwrap(), ["$KE,ADC,1", "$KE,ADC,2"]);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_adc_differential_native() -> Result<()> {
+		let caps = Capabilities {
+			adc_differential: true,
+		};
+		let opts = GatewayOptions::new().with_capabilities(caps);
+		let (gw, received) = scripted_with(&opts, |cmd| match cmd {
+			"$KE,ADC,DIF,1,2" => "#ADC,DIF,-1500".into(),
+			_ => "#ERR".into(),
+		});
+
+		assert_eq!(gw.adc_differential(1, 2).await?, -1500);
+		assert_eq!(*received.lock().unwrap(), ["$KE,ADC,DIF,1,2"]);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_adc_all() -> Result<()> {
+		let (gw, _) = scripted(|_| "#ADC,ALL,512,1023".into());
+		assert_eq!(gw.adc_all().await?, [512, 1023]);
+
+		let (gw, _) = scripted(|_| "#ADC,ALL,512".into());
+		assert!(matches!(gw.adc_all().await, Err(Error::InvalidPayload(_))));
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_rejects_zero_capacity() -> Result<()> {
+		let (client, _device) = tokio::io::duplex(64);
+		let opts = GatewayOptions::new().with_event_capacity(0);
+
+		assert!(matches!(
+			StreamGateway::connect_with(client, &opts),
+			Err(Error::InvalidPayload(_))
+		));
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_event_capacity() -> Result<()> {
+		let (client, device) = tokio::io::duplex(1024);
+		let opts = GatewayOptions::new().with_event_capacity(2);
+		let gw = StreamGateway::connect_with(client, &opts)?;
+		let mut sub = gw.subscibe();
+
+		tokio::spawn(async move {
+			let (rd, mut wr) = tokio::io::split(device);
+			let mut lines = BufReader::new(rd).lines();
+			let _ = lines.next_line().await;
+			// Events ahead of the reply, so they are all in by the time the ping returns.
+			for signal in [1, 0, 1, 0, 1] {
+				let event = format!("#M,EIN,1,{signal}\r\n");
+				wr.write_all(event.as_bytes()).await.unwrap();
+			}
+			wr.write_all(b"#OK\r\n").await.unwrap();
+			while let Ok(Some(_)) = lines.next_line().await {}
+		});
+
+		gw.ping().await?;
+		assert!(matches!(sub.recv().await, Err(RecvError::Lagged(3))));
+		assert_eq!(
+			sub.recv().await.unwrap(),
+			Event::Ein {
+				line: 1,
+				signal: Signal::Low,
+			}
+		);
+		assert_eq!(
+			sub.recv().await.unwrap(),
+			Event::Ein {
+				line: 1,
+				signal: Signal::High,
+			}
+		);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_reconcile_relays() -> Result<()> {
+		let (gw, received) = scripted(|cmd| match cmd {
+			"$KE,RDR,ALL" => "#RDR,ALL,1,0,1,0".into(),
+			cmd if cmd.starts_with("$KE,REL,") => "#REL,OK".into(),
+			_ => "#ERR".into(),
+		});
+
+		gw.reconcile_relays(&[true, false, true]).await?;
+		assert_eq!(*received.lock().unwrap(), ["$KE,RDR,ALL"]);
+
+		received.lock().unwrap().clear();
+		gw.reconcile_relays(&[true, true, false]).await?;
+		assert_eq!(
+			*received.lock().unwrap(),
+			["$KE,RDR,ALL", "$KE,REL,ALL,x10x"]
+		);
+
+		received.lock().unwrap().clear();
+		assert!(matches!(
+			gw.reconcile_relays(&vec![false; RELAY_COUNT as usize + 1])
+				.await,
+			Err(Error::InvalidPayload(_))
+		));
+		assert!(received.lock().unwrap().is_empty());
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_scan_onewire() -> Result<()> {
+		let (gw, _) = scripted(|cmd| match cmd {
+			"$KE,1WT,SCAN" => [
+				"#1WT,ROM,1,28FF4A3B91160312",
+				"#1WT,ROM,2,28FF0C2A6016046B",
+				"#1WT,SCAN,OK",
+			]
+			.join("\r\n"),
+			_ => "#ERR".into(),
+		});
+
+		assert_eq!(
+			gw.scan_onewire().await?,
+			[
+				OneWireSensor {
+					index: 1,
+					rom: 0x28FF4A3B91160312,
+				},
+				OneWireSensor {
+					index: 2,
+					rom: 0x28FF0C2A6016046B,
+				},
+			]
+		);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_advanced_input() -> Result<()> {
+		let (gw, _) = scripted(|cmd| match cmd {
+			"$KE,ADVC,1" => "#ADVC,1,26,2242469".into(),
+			_ => "#ERR".into(),
+		});
+
+		let data = gw.advanced_input(1).await?;
+		assert_eq!(data.wiegand26(), Some((18, 4660)));
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_connect_with_spawner() -> Result<()> {
+		let listener = TcpListener::bind("0.0.0.0:0").await?;
+		let addr = listener.local_addr()?;
+
+		let spawned = Arc::new(AtomicBool::new(false));
+		let flag = spawned.clone();
+		let gw = StreamGateway::connect_with_spawner(TcpStream::connect(addr).await?, |reader| {
+			flag.store(true, Ordering::SeqCst);
+			tokio::spawn(reader);
+		});
+		assert!(spawned.load(Ordering::SeqCst));
+
+		let (mut stream, _) = listener.accept().await.unwrap();
+
+		tokio::spawn(async move {
+			let mut buf = vec![0; 64];
+			let _ = stream.read(&mut buf).await.unwrap();
+			stream.write_all(b"#OK\r\n").await.unwrap();
+		});
+
+		gw.ping().await?;
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_output() -> Result<()> {
+		let level = std::sync::Mutex::new("0".to_owned());
+		let (gw, _) = scripted(move |cmd| match cmd.split(',').collect::<Vec<_>>()[..] {
+			["$KE", "OUT", "7", value] => {
+				*level.lock().unwrap() = value.to_owned();
+				"#OUT,OK".into()
+			}
+			["$KE", "OUT", "7"] => format!("#OUT,7,{}", level.lock().unwrap()),
+			_ => "#ERR".into(),
+		});
+
+		assert!(gw.output(7).await?.is_low());
+		gw.set_output(7, Signal::High).await?;
+		assert!(gw.output(7).await?.is_high());
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_pulse_output() -> Result<()> {
+		let (gw, received) = scripted(|cmd| match cmd {
+			"$KE,OUT,2,PLS,200,500" => "#OUT,OK".into(),
+			_ => "#ERR".into(),
+		});
+
+		gw.pulse_output(2, 200, 500).await?;
+		assert!(matches!(
+			gw.pulse_output(2, 10, PULSE_MAX_FREQ_HZ + 1).await,
+			Err(Error::InvalidPayload(_))
+		));
+		assert!(matches!(
+			gw.pulse_output(2, 0, 10).await,
+			Err(Error::InvalidPayload(_))
+		));
+		assert!(matches!(
+			gw.pulse_output(0, 1, 1).await,
+			Err(Error::InvalidPayload(_))
+		));
+		assert!(matches!(
+			gw.pulse_output(OUTPUT_COUNT + 1, 1, 1).await,
+			Err(Error::InvalidPayload(_))
+		));
+		assert!(matches!(
+			gw.pulse_output(3, 1, 1).await,
+			Err(Error::SyntaxError(_))
+		));
+		assert_eq!(
+			*received.lock().unwrap(),
+			["$KE,OUT,2,PLS,200,500", "$KE,OUT,3,PLS,1,1"]
+		);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_relay_link() -> Result<()> {
+		let (gw, received) = scripted(|cmd| match cmd {
+			"$KE,LNK,2,3,1" | "$KE,LNK,2,OFF" => "#LNK,OK".into(),
+			"$KE,LNK,2" => "#LNK,2,3,1".into(),
+			_ => "#ERR".into(),
+		});
+
+		gw.link_relay_to_input(2, 3, true).await?;
+		assert_eq!(
+			gw.relay_link(2).await?,
+			Some(RelayLink {
+				line: 3,
+				invert: true,
+			})
+		);
+		gw.unlink_relay(2).await?;
+		assert_eq!(
+			*received.lock().unwrap(),
+			["$KE,LNK,2,3,1", "$KE,LNK,2", "$KE,LNK,2,OFF"]
+		);
+
+		assert!(matches!(
+			gw.link_relay_to_input(5, 1, false).await,
+			Err(Error::InvalidPayload(_))
+		));
+		assert!(matches!(
+			gw.link_relay_to_input(1, 0, false).await,
+			Err(Error::InvalidPayload(_))
+		));
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_event_format_version() -> Result<()> {
+		let listener = TcpListener::bind("0.0.0.0:0").await?;
+		let addr = listener.local_addr()?;
+
+		let gw = StreamGateway::connect(TcpStream::connect(addr).await?);
+		let mut sub = gw.subscibe();
+
+		let (mut stream, _) = listener.accept().await.unwrap();
+
+		tokio::spawn(async move {
+			let mut buf = vec![0; 64];
+			let _ = stream.read(&mut buf).await.unwrap();
+			stream.write_all(b"#MSG,VER,2\r\n").await.unwrap();
+			stream.write_all(b"#M,86400,EIN,3,1\r\n").await.unwrap();
+		});
+
+		assert_eq!(gw.event_format_version().await?, 2);
+		assert_eq!(
+			sub.recv().await.unwrap(),
+			Event::Ein {
+				line: 3,
+				signal: Signal::High,
+			}
+		);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_raw_lines() -> Result<()> {
+		let opts = GatewayOptions::new().with_raw_tap(true);
+		let (gw, _) = scripted_with(&opts, |_| "#INF, Laurent-2 ;v2".into());
+		let mut raw = gw.raw_lines().unwrap();
+
+		gw.raw_command(vec!["INF".into()]).await?;
+		assert_eq!(
+			raw.recv().await.unwrap(),
+			b"#INF, Laurent-2 ;v2\r\n".as_slice()
+		);
+
+		let (gw, _) = scripted(|_| "#OK".into());
+		assert!(gw.raw_lines().is_none());
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_event_parts() -> Result<()> {
+		let (client, mut device) = tokio::io::duplex(1024);
+		let opts = GatewayOptions::new().with_event_parts(true);
+		let gw = StreamGateway::connect_with(client, &opts)?;
+		let mut sub = gw.subscribe_with_parts().unwrap();
+
+		device.write_all(b"#M,EIN,2,1\r\n").await?;
+		let raw = sub.recv().await.unwrap();
+		assert_eq!(
+			raw.event,
+			Event::Ein {
+				line: 2,
+				signal: Signal::High,
+			}
+		);
+		assert_eq!(raw.parts, ["#M", "EIN", "2", "1"]);
+
+		let (gw, _) = scripted(|_| "#OK".into());
+		assert!(gw.subscribe_with_parts().is_none());
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_reconcile_relays_fallback() -> Result<()> {
+		let (gw, received) = scripted(|cmd| match cmd {
+			"$KE,RDR,1" => "#RDR,1,0".into(),
+			"$KE,RDR,2" => "#RDR,2,1".into(),
+			"$KE,REL,1,1" | "$KE,REL,2,0" => "#REL,OK".into(),
+			_ => "#ERR".into(),
+		});
+
+		gw.reconcile_relays(&[true, false]).await?;
+		assert_eq!(
+			*received.lock().unwrap(),
+			[
+				"$KE,RDR,ALL",
+				"$KE,RDR,1",
+				"$KE,RDR,2",
+				"$KE,REL,ALL,10xx",
+				"$KE,REL,1,1",
+				"$KE,REL,2,0"
+			]
+		);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_detect_capabilities() -> Result<()> {
+		let (gw, _) = scripted(|cmd| match cmd {
+			"$KE,HELP" => [
+				"#HELP,REL,RDR,RD",
+				"#HELP,ADC,ADC DIF,1WT,1WT SCAN",
+				"#HELP,END",
+			]
+			.join("\r\n"),
+			_ => "#ERR".into(),
+		});
+
+		assert_eq!(
+			gw.supported_commands().await?,
+			["REL", "RDR", "RD", "ADC", "ADC DIF", "1WT", "1WT SCAN"]
+		);
+		assert!(!gw.capabilities().adc_differential);
+		assert!(gw.detect_capabilities().await?.adc_differential);
+		assert!(gw.capabilities().adc_differential);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_temperature_extremes() -> Result<()> {
+		let (gw, received) = scripted(|cmd| match cmd {
+			"$KE,1WT,2,EXT" => "#1WT,2,-4.5,27.25".into(),
+			"$KE,1WT,2,EXT,RST" => "#1WT,OK".into(),
+			_ => "#ERR".into(),
+		});
+
+		assert_eq!(gw.temperature_extremes(2).await?, (-4.5, 27.25));
+		gw.reset_temperature_extremes(2).await?;
+		assert_eq!(
+			*received.lock().unwrap(),
+			["$KE,1WT,2,EXT", "$KE,1WT,2,EXT,RST"]
+		);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_adc_stream() -> Result<()> {
+		let (gw, received) = scripted(|cmd| match cmd {
+			"$KE,ADC,2,STR,100" => ["#ADC,OK", "#M,ADC,1,7", "#M,ADC,2,512", "#M,ADC,2,514"].join("\r\n"),
+			"$KE,ADC,2,STR,OFF" => "#ADC,OK".into(),
+			"$KE" => "#OK".into(),
+			_ => "#ERR".into(),
+		});
+
+		let samples = gw.adc_stream(2, 100).await?;
+		let samples: Vec<_> = samples.take(2).collect().await;
+		assert_eq!(samples.into_iter().collect::<Result<Vec<_>>>()?, [512, 514]);
+
+		// The stream is gone, so sampling was switched off before the ping went out.
+		gw.ping().await?;
+		assert_eq!(
+			*received.lock().unwrap(),
+			["$KE,ADC,2,STR,100", "$KE,ADC,2,STR,OFF", "$KE"]
+		);
+
+		assert!(matches!(
+			gw.adc_stream(2, 0).await,
+			Err(Error::InvalidPayload(_))
+		));
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_last_heartbeat() -> Result<()> {
+		let (gw, _) = scripted(|_| ["#M,HB", "#OK"].join("\r\n"));
+		let mut sub = gw.subscibe();
+		assert_eq!(gw.last_heartbeat(), None);
+
+		let before = Instant::now();
+		gw.ping().await?;
+		assert!(gw.last_heartbeat().is_some_and(|at| at >= before));
+		assert_eq!(sub.recv().await.unwrap(), Event::Heartbeat);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_lenient() -> Result<()> {
+		let reply = |cmd: &str| match cmd {
+			"$KE,RDR,1" => "#RDR,1,1,LOCKED".into(),
+			_ => "#ERR".into(),
+		};
+
+		let (gw, _) = scripted(reply);
+		assert!(matches!(
+			gw.relay_status(1).await,
+			Err(Error::UnknownMessage(_))
+		));
+
+		let opts = GatewayOptions::new().with_lenient(true);
+		let (gw, _) = scripted_with(&opts, reply);
+		match gw.relay_status(1).await {
+			Err(Error::Unrecognized(parts)) => assert_eq!(parts, ["#RDR", "1", "1", "LOCKED"]),
+			res => panic!("Unexpected result: {res:?}"),
+		}
+		assert!(matches!(
+			gw.relay_status(2).await,
+			Err(Error::SyntaxError(_))
+		));
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_errors_show_reply() -> Result<()> {
+		let (gw, _) = scripted(|cmd| match cmd {
+			"$KE" => "#WAT,1".into(),
+			"$KE,RDR,1" => "#RDR,WAT".into(),
+			"$KE,RDR,2" => "#RDR,3,1".into(),
+			_ => "#ERR".into(),
+		});
+
+		let err = gw.ping().await.unwrap_err();
+		assert!(err.to_string().contains("WAT"), "{err}");
+
+		let err = gw.relay_status(1).await.unwrap_err();
+		assert!(matches!(err, Error::UnknownMessage(_)));
+		assert!(err.to_string().contains("WAT"), "{err}");
+
+		match gw.relay_status(2).await {
+			Err(Error::UnexpectedMessage(parts)) => assert_eq!(parts, ["#RDR", "3", "1"]),
+			res => panic!("Unexpected result: {res:?}"),
+		}
+
+		match gw.relay_status(4).await {
+			Err(Error::SyntaxError(parts)) => assert_eq!(parts, ["#ERR"]),
+			res => panic!("Unexpected result: {res:?}"),
+		}
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_event_interval() -> Result<()> {
+		let flood = [
+			"#M,EIN,1,1",
+			"#M,EIN,1,0",
+			"#M,EIN,1,1",
+			"#M,EIN,2,1",
+			"#M,EIN,1,0",
+			"#OK",
+		]
+		.join("\r\n");
+		let opts = GatewayOptions::new().with_event_interval(Duration::from_secs(60));
+		let (gw, _) = scripted_with(&opts, move |_| flood.clone());
+		let mut sub = gw.subscibe();
+
+		gw.ping().await?;
+		let ein = |line, signal| Event::Ein { line, signal };
+		assert_eq!(sub.try_recv().unwrap(), ein(1, Signal::High));
+		assert_eq!(sub.try_recv().unwrap(), ein(2, Signal::High));
+		assert!(sub.try_recv().is_err());
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_relay_cycle_count() -> Result<()> {
+		let (gw, _) = scripted(|cmd| match cmd {
+			"$KE,RCC,3" => "#RCC,3,120455".into(),
+			"$KE,RCC,4" => "#RCC,3,7".into(),
+			_ => "#ERR".into(),
+		});
+
+		assert_eq!(gw.relay_cycle_count(3).await?, 120455);
+		assert!(matches!(
+			gw.relay_cycle_count(4).await,
+			Err(Error::UnexpectedMessage(_))
+		));
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_suppress_events() -> Result<()> {
+		let (gw, received) = scripted(|cmd| match cmd {
+			"$KE,MSG,S,RELE,SET,OFF" | "$KE,MSG,S,EIN,SET,OFF" => "#MSG,SET,OK".into(),
+			"$KE,MSG,S,RELE,SET,ON" | "$KE,MSG,S,EIN,SET,ON" => "#MSG,SET,OK".into(),
+			"$KE,REL,1,1" => "#REL,OK".into(),
+			_ => "#ERR".into(),
+		});
+		let gw = Arc::new(gw);
+
+		let quiet = gw
+			.suppress_events(&[EventKind::Rele, EventKind::Ein])
+			.await?;
+		gw.relay(1, RelayAction::On, None).await?;
+		assert_eq!(
+			*received.lock().unwrap(),
+			[
+				"$KE,MSG,S,RELE,SET,OFF",
+				"$KE,MSG,S,EIN,SET,OFF",
+				"$KE,REL,1,1"
+			]
+		);
+
+		drop(quiet);
+		tokio::time::timeout(Duration::from_secs(1), async {
+			while received.lock().unwrap().len() < 5 {
+				tokio::task::yield_now().await;
+			}
+		})
+		.await
+		.unwrap();
+		assert_eq!(
+			received.lock().unwrap()[3..],
+			["$KE,MSG,S,RELE,SET,ON", "$KE,MSG,S,EIN,SET,ON"]
+		);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_suppress_events_across_reconnect() -> Result<()> {
+		let device = FakeDevice::new();
+		let connect = {
+			let device = device.clone();
+			move || {
+				let stream = device.serve();
+				async move { Ok(stream) }
+			}
+		};
+		let gw = StreamGateway::connect_reconnecting(connect, &GatewayOptions::new()).await?;
+		let gw = Arc::new(gw);
+		let mut state = gw.connection_state();
+		let mut sub = gw.subscibe();
+		gw.cfg_event(EventKind::Ein, true).await?;
+
+		drop(gw.suppress_events(&[EventKind::Ein]).await?);
+		// Toggles the line until the guard's task has turned the events back on.
+		let mut signal = Signal::Low;
+		tokio::time::timeout(Duration::from_secs(1), async {
+			loop {
+				signal = !signal;
+				device.set_input(1, signal).unwrap();
+				let event = tokio::time::timeout(Duration::from_millis(20), sub.recv()).await;
+				if event.is_ok() {
+					break;
+				}
+			}
+		})
+		.await
+		.unwrap();
+		gw.ping().await?;
+		while sub.try_recv().is_ok() {}
+
+		// The restored kind is turned on again on the new connection.
+		device.disconnect();
+		state.changed().await.unwrap();
+		state
+			.wait_for(|state| *state == ConnectionState::Connected)
+			.await
+			.unwrap();
+		gw.ping().await?;
+		device.set_input(1, !signal)?;
+		let event = tokio::time::timeout(Duration::from_secs(1), sub.recv()).await;
+		assert_eq!(
+			event.unwrap().unwrap(),
+			Event::Ein {
+				line: 1,
+				signal: !signal,
+			}
+		);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_firmware_details() -> Result<()> {
+		let (gw, _) = scripted(|_| "#INF,Laurent-2,2.14,2023-05-11,A3F09C21".into());
+		let info = gw.firmware_details().await?;
+		assert_eq!(info.model, "Laurent-2");
+		assert_eq!(info.version.as_deref(), Some("2.14"));
+		assert_eq!(info.build_date.as_deref(), Some("2023-05-11"));
+		assert_eq!(info.checksum.as_deref(), Some("A3F09C21"));
+
+		let (gw, _) = scripted(|_| "#INF,Laurent-2".into());
+		assert_eq!(
+			gw.firmware_details().await?,
+			FirmwareInfo {
+				model: "Laurent-2".into(),
+				..Default::default()
+			}
+		);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_module_info() -> Result<()> {
+		let (gw, received) = scripted(|_| "#INF,Laurent-2".into());
+		assert_eq!(gw.module_info().await?, "Laurent-2");
+		assert_eq!(*received.lock().unwrap(), ["$KE,INF"]);
+
+		let (gw, _) = scripted(|_| "#ERR".into());
+		assert!(matches!(gw.module_info().await, Err(Error::SyntaxError(_))));
+		let (gw, _) = scripted(|_| "#INF".into());
+		assert!(matches!(
+			gw.module_info().await,
+			Err(Error::UnknownMessage(_))
+		));
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_strict() -> Result<()> {
+		let reply = |cmd: &str| match cmd {
+			"$KE,RDR,1" => "#RDR,1,1,EXTRA".into(),
+			"$KE,RDR,2" => "#RDR,2,1".into(),
+			"$KE,TMP" => "#TMP,ERR,EXTRA".into(),
+			_ => "#ERR".into(),
+		};
+
+		let (gw, _) = scripted(reply);
+		assert!(matches!(
+			gw.relay_status(1).await,
+			Err(Error::UnknownMessage(_))
+		));
+		assert!(matches!(
+			gw.board_temperature().await,
+			Err(Error::UnknownMessage(_))
+		));
+		assert!(gw.relay_status(2).await?);
+
+		let opts = GatewayOptions::new().with_lenient(true);
+		let (gw, _) = scripted_with(&opts, reply);
+		match gw.relay_status(1).await {
+			Err(Error::Unrecognized(parts)) => assert_eq!(parts, ["#RDR", "1", "1", "EXTRA"]),
+			res => panic!("Unexpected result: {res:?}"),
+		}
+
+		let opts = GatewayOptions::new().with_strict(true);
+		let (gw, _) = scripted_with(&opts, reply);
+		assert!(matches!(
+			gw.relay_status(1).await,
+			Err(Error::UnexpectedMessage(_))
+		));
+		assert!(matches!(
+			gw.board_temperature().await,
+			Err(Error::UnexpectedMessage(_))
+		));
+		assert!(gw.relay_status(2).await?);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_command_watchdog() -> Result<()> {
+		let (gw, received) = scripted(|cmd| match cmd {
+			"$KE,WDT,30" | "$KE,WDT,OFF" => "#WDT,OK".into(),
+			_ => "#ERR".into(),
+		});
+
+		gw.set_command_watchdog(Some(Duration::from_secs(30)))
+			.await?;
+		assert_eq!(gw.command_watchdog(), Some(Duration::from_secs(30)));
+		gw.set_command_watchdog(None).await?;
+		assert_eq!(gw.command_watchdog(), None);
+		for window in [Duration::from_millis(500), Duration::from_millis(1900)] {
+			assert!(matches!(
+				gw.set_command_watchdog(Some(window)).await,
+				Err(Error::InvalidPayload(_))
+			));
+		}
+		assert_eq!(*received.lock().unwrap(), ["$KE,WDT,30", "$KE,WDT,OFF"]);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_export_config() -> Result<()> {
+		let (gw, _) = scripted(|cmd| match cmd {
+			"$KE,CFG,ALL" => ["#CFG,IP,192.168.0.101", "#CFG,PORT,2424", "#CFG,END"].join("\r\n"),
+			_ => "#ERR".into(),
+		});
+
+		assert_eq!(gw.export_config().await?, "IP,192.168.0.101\nPORT,2424");
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_import_config() -> Result<()> {
+		let (client, device) = tokio::io::duplex(1024);
+		let gw = StreamGateway::connect(client);
+
+		let device = tokio::spawn(async move {
+			let (rd, mut wr) = tokio::io::split(device);
+			let mut lines = BufReader::new(rd).lines();
+			let mut received = Vec::new();
+
+			while let Some(line) = lines.next_line().await.unwrap() {
+				if line == "$KE,CFG,APPLY" {
+					// Reboots without answering.
+					break;
+				}
+				received.push(line);
+				wr.write_all(b"#CFG,OK\r\n").await.unwrap();
+			}
+			received
+		});
+
+		gw.import_config("IP,192.168.0.101\n\nPORT,2424\n").await?;
+		assert_eq!(
+			device.await.unwrap(),
+			["$KE,CFG,SET,IP,192.168.0.101", "$KE,CFG,SET,PORT,2424"]
+		);
+
+		let (gw, _) = scripted(|_| "#ERR".into());
+		assert!(matches!(
+			gw.import_config("PORT,0").await,
+			Err(Error::InvalidPayload(_))
+		));
+
+		Ok(())
+	}
+
+	/// The rejection behind a result, if the device answered with a coded `#ERR`.
+	fn rejection<T>(res: Result<T>) -> Option<Rejection> {
+		match res {
+			Err(Error::Rejected(rejection)) => Some(rejection),
+			_ => None,
+		}
+	}
+
+	#[tokio::test]
+	async fn gateway_ping_rejection() -> Result<()> {
+		let (gw, _) = scripted(|_| "#ERR,IDX".into());
+
+		// Only commands with an index know the code for a wrong one.
+		assert_eq!(
+			rejection(gw.ping().await),
+			Some(Rejection::Other("IDX".into()))
+		);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_relay_rejections() -> Result<()> {
+		let (gw, _) = scripted(|cmd| match cmd {
+			"$KE,REL,9,1" => "#ERR,IDX".into(),
+			"$KE,REL,1,1" => "#ERR,PSW".into(),
+			_ => "#ERR".into(),
+		});
+
+		assert_eq!(
+			rejection(gw.relay(9, RelayAction::On, None).await),
+			Some(Rejection::InvalidIndex)
+		);
+		assert_eq!(
+			rejection(gw.relay(1, RelayAction::On, None).await),
+			Some(Rejection::NotAuthorized)
+		);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_relay_status_rejection() -> Result<()> {
+		let (gw, _) = scripted(|_| "#ERR,IDX".into());
+
+		assert_eq!(
+			rejection(gw.relay_status(9).await),
+			Some(Rejection::InvalidIndex)
+		);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_line_signal_rejection() -> Result<()> {
+		let (gw, _) = scripted(|_| "#ERR,XYZ,2".into());
+
+		assert_eq!(
+			rejection(gw.line_signal(1).await),
+			Some(Rejection::Other("XYZ,2".into()))
+		);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_pulse_output_rejections() -> Result<()> {
+		let (gw, _) = scripted(|cmd| match cmd {
+			"$KE,OUT,1,PLS,5,10" => "#ERR,CNT".into(),
+			_ => "#ERR".into(),
+		});
+
+		assert_eq!(
+			rejection(gw.pulse_output(1, 5, 10).await),
+			Some(Rejection::InvalidArgument)
+		);
+		assert!(matches!(
+			gw.pulse_output(2, 5, 10).await,
+			Err(Error::SyntaxError(_))
+		));
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_write_relays_rejection() -> Result<()> {
+		let (gw, _) = scripted(|_| "#ERR,ARG".into());
+
+		assert_eq!(
+			rejection(gw.write_relays(0b1, 0b1).await),
+			Some(Rejection::InvalidArgument)
+		);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_command_watchdog_rejection() -> Result<()> {
+		let (gw, _) = scripted(|_| "#ERR,BSY".into());
+
+		assert_eq!(
+			rejection(gw.set_command_watchdog(Some(Duration::from_secs(5))).await),
+			Some(Rejection::Busy)
+		);
+
+		Ok(())
+	}
 
-impl StreamGateway {
-	pub fn connect<T>(stream: T) -> Self
-	where
-		T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
-	{
-		let (cmd_tx, mut cmd_tx_rx) = mpsc::channel(1);
-		let (cmd_rx_tx, cmd_rx) = mpsc::channel(1);
-		let (events, _) = broadcast::channel(1024);
+	#[tokio::test]
+	async fn gateway_save_settings_rejection() -> Result<()> {
+		let (gw, _) = scripted(|_| "#ERR".into());
 
-		let event_tx = events.clone();
-		tokio::spawn(async move {
-			let mut stream = Framed::new(stream, Codec::new());
+		assert!(matches!(
+			gw.save_settings().await,
+			Err(Error::SyntaxError(_))
+		));
 
-			loop {
-				tokio::select! {
-					Some(msg) = stream.next() => {
-						match msg.as_deref() {
-							Ok([ty, rest @ ..]) if is_event(ty) => {
-								if let Ok(event) = Event::try_from(rest) {
-									if event_tx.send(event).is_err() {
-										break;
-									}
-								}
-							},
-							_ => {
-								if cmd_rx_tx.send(msg).await.is_err() {
-									break;
-								}
-							},
-						}
-					},
-					cmd = cmd_tx_rx.recv() => {
-						if let Some(cmd) = cmd {
-							stream.send(cmd).await.expect("Failed to send command");
-						}
-					}
-				}
-			}
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_save_settings() -> Result<()> {
+		let (client, device) = tokio::io::duplex(1024);
+		let gw = StreamGateway::connect(client);
+
+		let (rd, mut wr) = tokio::io::split(device);
+		let device = tokio::spawn(async move {
+			let mut lines = BufReader::new(rd).lines();
+			let line = lines.next_line().await.unwrap();
+			wr.write_all(b"#SAVE,OK\r\n").await.unwrap();
+			line
 		});
 
-		Self {
-			cmd_rx: Mutex::new(cmd_rx),
-			cmd_tx,
-			events,
-		}
-	}
+		gw.save_settings().await?;
+		assert_eq!(device.await.unwrap().as_deref(), Some("$KE,SAVE"));
 
-	async fn send<T>(&self, cmd: T) -> Result<()>
-	where
-		T: JoinParts + Send + 'static,
-	{
-		self.cmd_tx.send(cmd.boxed()).await.map_err(|_| Error::Send)
+		Ok(())
 	}
 
-	async fn recv(&self) -> Result<Vec<String>> {
-		match self.cmd_rx.lock().await.recv().await {
-			Some(Ok(msg)) => Ok(msg),
-			_ => Err(Error::Closed),
-		}
-	}
-}
+	#[tokio::test]
+	async fn gateway_read_counter() -> Result<()> {
+		let (gw, received) = scripted(|cmd| match cmd {
+			"$KE,CNT,1" => "#CNT,1,42".into(),
+			"$KE,CNT,2" => "#CNT,3,42".into(),
+			"$KE,CNT,1,RST" => "#CNT,1,OK".into(),
+			_ => "#ERR".into(),
+		});
 
-#[async_trait]
-impl Gateway for StreamGateway {
-	fn subscibe(&self) -> BroadcastReceiver<Event> {
-		self.events.subscribe()
+		assert_eq!(gw.read_counter(1).await?, 42);
+		assert!(matches!(
+			gw.read_counter(2).await,
+			Err(Error::UnexpectedMessage(_))
+		));
+		gw.reset_counter(1).await?;
+		assert_eq!(
+			*received.lock().unwrap(),
+			["$KE,CNT,1", "$KE,CNT,2", "$KE,CNT,1,RST"]
+		);
+
+		Ok(())
 	}
 
-	async fn ping(&self) -> Result<()> {
-		self.send(("$KE",)).await?;
+	#[tokio::test]
+	async fn gateway_set_pwm() -> Result<()> {
+		let (gw, received) = scripted(|cmd| match cmd {
+			"$KE,PWM,1,50" => "#PWM,OK".into(),
+			_ => "#ERR".into(),
+		});
 
-		match as_match!(self.recv().await?) {
-			["#OK"] => Ok(()),
-			["#ERR"] => Err(Error::SyntaxError),
-			_ => Err(Error::UnknownMessage),
-		}
+		gw.set_pwm(1, 50).await?;
+		assert!(matches!(
+			gw.set_pwm(1, 101).await,
+			Err(Error::InvalidPayload(_))
+		));
+		assert!(matches!(
+			gw.set_pwm(0, 50).await,
+			Err(Error::InvalidPayload(_))
+		));
+		assert!(matches!(
+			gw.set_pwm(PWM_COUNT + 1, 50).await,
+			Err(Error::InvalidPayload(_))
+		));
+		assert_eq!(*received.lock().unwrap(), ["$KE,PWM,1,50"]);
+
+		let (client, mut device) = tokio::io::duplex(1024);
+		let gw = StreamGateway::connect(client);
+		let mut sub = gw.subscibe();
+
+		device.write_all(b"#M,PWM,1,75\r\n").await?;
+		assert_eq!(
+			sub.recv().await.unwrap(),
+			Event::Pwm {
+				channel: 1,
+				duty: 75,
+			}
+		);
+
+		Ok(())
 	}
 
-	async fn authorize(&self, pwd: &str) -> Result<()> {
-		self.send(("$KE", "PSW", "SET", pwd.to_owned())).await?;
+	#[tokio::test]
+	async fn gateway_reboot() -> Result<()> {
+		let (client, device) = tokio::io::duplex(1024);
+		let gw = StreamGateway::connect(client);
 
-		match as_match!(self.recv().await?) {
-			["#PSW", "SET", "OK"] => Ok(()),
-			["#PSW", "SET", "ERR"] => Err(Error::Auth),
-			["#ERR"] => Err(Error::SyntaxError),
-			_ => Err(Error::UnknownMessage),
-		}
+		let device = tokio::spawn(async move {
+			let mut lines = BufReader::new(device).lines();
+			// Goes down without answering.
+			lines.next_line().await.unwrap()
+		});
+
+		gw.reboot().await?;
+		assert_eq!(device.await.unwrap().as_deref(), Some("$KE,RST"));
+
+		let (gw, _) = scripted(|_| "#RST,OK".into());
+		gw.reboot().await?;
+
+		Ok(())
 	}
 
-	async fn cfg_event(&self, kind: EventKind, enabled: bool) -> Result<()> {
-		let state = if enabled { "ON" } else { "OFF" };
-		self.send(("$KE", "MSG", "S", kind, "SET", state)).await?;
+	#[tokio::test]
+	async fn gateway_connect_shared() -> Result<()> {
+		let (client, device) = tokio::io::duplex(1024);
+		let gw = StreamGateway::connect_shared(client);
 
-		match as_match!(self.recv().await?) {
-			["#MSG", "SET", "OK"] => Ok(()),
-			["#ERR"] => Err(Error::SyntaxError),
-			_ => Err(Error::UnknownMessage),
-		}
+		tokio::spawn(async move {
+			let (rd, mut wr) = tokio::io::split(device);
+			let mut lines = BufReader::new(rd).lines();
+			while let Ok(Some(line)) = lines.next_line().await {
+				let reply = match line.as_str() {
+					"$KE,RDR,1" => "#RDR,1,1\r\n",
+					"$KE,RD,2" => "#RD,2,0\r\n",
+					_ => "#ERR\r\n",
+				};
+				wr.write_all(reply.as_bytes()).await.unwrap();
+			}
+		});
+
+		let relay = crate::Relay::new(gw.clone(), 1);
+		let line = crate::InputLine::new(gw, 2);
+		assert!(relay.status().await?);
+		assert_eq!(line.read_signal().await?, Signal::Low);
+
+		Ok(())
 	}
 
-	async fn relay(&self, relay: u32, action: RelayAction, delay: Option<ClickDelay>) -> Result<()> {
-		match delay {
-			None => self.send(("$KE", "REL", relay, action)).await?,
-			Some(delay) => self.send(("$KE", "REL", relay, action, delay)).await?,
-		}
+	#[tokio::test]
+	async fn gateway_relay_timers() -> Result<()> {
+		let (gw, _) = scripted(|cmd| match cmd {
+			"$KE,RTM,ALL" => "#RTM,ALL,25,-,0,-".into(),
+			_ => "#ERR".into(),
+		});
 
-		match as_match!(self.recv().await?) {
-			["#REL", "OK"] => Ok(()),
-			["#ERR"] => Err(Error::SyntaxError),
-			_ => Err(Error::UnknownMessage),
-		}
+		assert_eq!(
+			gw.relay_timers().await?,
+			[
+				Some(Duration::from_millis(2500)),
+				None,
+				Some(Duration::ZERO),
+				None
+			]
+		);
+
+		let (gw, _) = scripted(|_| "#RTM,ALL,25,-".into());
+		assert!(matches!(
+			gw.relay_timers().await,
+			Err(Error::InvalidPayload(_))
+		));
+
+		Ok(())
 	}
 
-	async fn relay_status(&self, relay: u32) -> Result<bool> {
-		self.send(("$KE", "RDR", relay)).await?;
+	#[tokio::test]
+	async fn gateway_skips_long_line() -> Result<()> {
+		let (gw, _) = scripted(|cmd| match cmd {
+			"$KE,INF" => format!("#INF,{}", "x".repeat(2000)),
+			_ => "#OK".into(),
+		});
 
-		match as_match!(self.recv().await?) {
-			["#RDR", rid, on] if rid.parse::<u32>()? == relay => Ok(on == "1"),
-			["#RDR", _, _] => Err(Error::UnexpectedMessage),
-			["#ERR"] => Err(Error::SyntaxError),
-			_ => Err(Error::UnknownMessage),
-		}
+		assert!(matches!(
+			gw.raw_command(vec!["INF".into()]).await,
+			Err(Error::MaxLineLengthExceeded { .. })
+		));
+		gw.ping().await?;
+
+		Ok(())
 	}
 
-	async fn line_signal(&self, line: u32) -> Result<Signal> {
-		self.send(("$KE", "RD", line)).await?;
+	#[tokio::test]
+	async fn gateway_concurrent_replies() -> Result<()> {
+		let (gw, _) = scripted(|cmd| match cmd.strip_prefix("$KE,RDR,") {
+			Some(relay) => format!("#RDR,{relay},1"),
+			None => "#ERR".into(),
+		});
+		let gw = Arc::new(gw);
 
-		match as_match!(self.recv().await?) {
-			["#RD", lid, on] if lid.parse::<u32>()? == line => Ok(on.parse()?),
-			["#RD", _, _] => Err(Error::UnexpectedMessage),
-			["#ERR"] => Err(Error::SyntaxError),
-			_ => Err(Error::UnknownMessage),
+		let tasks: Vec<_> = (0..64)
+			.map(|i| {
+				let gw = gw.clone();
+				tokio::spawn(async move { gw.relay_status(i % RELAY_COUNT + 1).await })
+			})
+			.collect();
+
+		for task in tasks {
+			assert!(task.await.unwrap()?);
 		}
-	}
-}
 
-#[cfg(test)]
-mod tests {
-	use super::*;
-	use crate::Signal;
-	use tokio::io::AsyncReadExt;
-	use tokio::io::AsyncWriteExt;
-	use tokio::net::TcpListener;
-	use tokio::net::TcpStream;
+		Ok(())
+	}
 
 	#[tokio::test]
-	async fn gateway_ping() -> Result<()> {
-		let listener = TcpListener::bind("0.0.0.0:0").await?;
+	async fn gateway_connect_tcp() -> Result<()> {
+		let listener = TcpListener::bind("127.0.0.1:0").await?;
 		let addr = listener.local_addr()?;
+		let opts = GatewayOptions::new()
+			.with_nodelay(true)
+			.with_tcp_keepalive(Duration::from_secs(30));
 
-		let gw = StreamGateway::connect(TcpStream::connect(addr).await?);
+		// A duplicate handle shares the socket options of the stream handed to the gateway.
+		let stream = TcpStream::connect(addr).await?.into_std()?;
+		let probe = stream.try_clone()?;
+		assert!(!probe.nodelay()?);
 
-		let (mut stream, _) = listener.accept().await.unwrap();
+		let gw = StreamGateway::connect_tcp(TcpStream::from_std(stream)?, &opts)?;
+		assert!(probe.nodelay()?);
+		assert!(SockRef::from(&probe).keepalive()?);
 
+		let (mut device, _) = listener.accept().await?;
 		tokio::spawn(async move {
 			let mut buf = vec![0; 64];
-			let _ = stream.read(&mut buf).await.unwrap();
-			stream.write_all(b"#OK\r\n").await.unwrap();
+			let _ = device.read(&mut buf).await.unwrap();
+			device.write_all(b"#OK\r\n").await.unwrap();
 		});
-
 		gw.ping().await?;
 
 		Ok(())
 	}
 
 	#[tokio::test]
-	async fn gateway_events() -> Result<()> {
-		let listener = TcpListener::bind("0.0.0.0:0").await?;
+	async fn gateway_connect_addr() -> Result<()> {
+		let listener = TcpListener::bind("127.0.0.1:0").await?;
 		let addr = listener.local_addr()?;
 
-		let gw = StreamGateway::connect(TcpStream::connect(addr).await?);
-
-		let (mut stream, _) = listener.accept().await.unwrap();
-
+		let gw = StreamGateway::connect_addr(addr).await?;
+		let (mut device, _) = listener.accept().await?;
 		tokio::spawn(async move {
-			stream.write_all(b"#M,EIN,1,1\r\n").await.unwrap();
-			stream.write_all(b"#M,EIN,1,0\r\n").await.unwrap();
-			stream.write_all(b"#M,EIN,2,1\r\n").await.unwrap();
-			stream.write_all(b"#M,EIN,2,0\r\n").await.unwrap();
+			let mut buf = vec![0; 64];
+			let _ = device.read(&mut buf).await.unwrap();
+			device.write_all(b"#OK\r\n").await.unwrap();
 		});
+		gw.ping().await?;
 
-		let mut sub = gw.subscibe();
-
-		let event = sub.recv().await.unwrap();
-		assert_eq!(
-			event,
-			Event::Ein {
-				line: 1,
-				signal: Signal::High,
-			}
-		);
-		let event = sub.recv().await.unwrap();
-		assert_eq!(
-			event,
-			Event::Ein {
-				line: 1,
-				signal: Signal::Low,
-			}
-		);
-		let event = sub.recv().await.unwrap();
-		assert_eq!(
-			event,
-			Event::Ein {
-				line: 2,
-				signal: Signal::High,
-			}
-		);
-		let event = sub.recv().await.unwrap();
-		assert_eq!(
-			event,
-			Event::Ein {
-				line: 2,
-				signal: Signal::Low,
-			}
-		);
+		drop(listener);
+		assert!(matches!(
+			StreamGateway::connect_addr(addr).await,
+			Err(Error::Io(_))
+		));
 
 		Ok(())
 	}
 
+	#[cfg(feature = "tls")]
 	#[tokio::test]
-	async fn gateway_relay() -> Result<()> {
-		let listener = TcpListener::bind("0.0.0.0:0").await?;
-		let addr = listener.local_addr()?;
+	async fn gateway_connect_tls() -> Result<()> {
+		use tokio_rustls::rustls;
 
-		let gw = StreamGateway::connect(TcpStream::connect(addr).await?);
+		let cert = rcgen::generate_simple_self_signed(vec!["laurent.test".into()]).unwrap();
+		let cert_der = cert.cert.der().clone();
+		let key_der = rustls::pki_types::PrivateKeyDer::Pkcs8(cert.key_pair.serialize_der().into());
 
-		let (mut stream, _) = listener.accept().await.unwrap();
+		let provider = Arc::new(rustls::crypto::ring::default_provider());
+		let server = rustls::ServerConfig::builder_with_provider(provider.clone())
+			.with_safe_default_protocol_versions()
+			.unwrap()
+			.with_no_client_auth()
+			.with_single_cert(vec![cert_der.clone()], key_der)
+			.unwrap();
+		let mut roots = rustls::RootCertStore::empty();
+		roots.add(cert_der).unwrap();
+		let client = rustls::ClientConfig::builder_with_provider(provider)
+			.with_safe_default_protocol_versions()
+			.unwrap()
+			.with_root_certificates(roots)
+			.with_no_client_auth();
 
+		let listener = TcpListener::bind("127.0.0.1:0").await?;
+		let addr = listener.local_addr()?;
+		let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(server));
 		tokio::spawn(async move {
+			let (stream, _) = listener.accept().await.unwrap();
+			let mut device = acceptor.accept(stream).await.unwrap();
 			let mut buf = vec![0; 64];
-			let _ = stream.read(&mut buf).await.unwrap();
-			stream.write_all(b"#REL,OK\r\n").await.unwrap();
+			let _ = device.read(&mut buf).await.unwrap();
+			device.write_all(b"#OK\r\n").await.unwrap();
+			device.flush().await.unwrap();
 		});
 
-		gw.relay(1, RelayAction::On, None).await?;
+		let gw = StreamGateway::connect_tls(addr, "laurent.test", Arc::new(client)).await?;
+		gw.ping().await?;
 
 		Ok(())
 	}
 
+	#[cfg(all(feature = "serial", unix))]
 	#[tokio::test]
-	async fn gateway_status() -> Result<()> {
-		let listener = TcpListener::bind("0.0.0.0:0").await?;
-		let addr = listener.local_addr()?;
+	async fn gateway_connect_serial() -> Result<()> {
+		let (client, mut device) = tokio_serial::SerialStream::pair().unwrap();
+		let gw = StreamGateway::connect(client);
 
-		let gw = StreamGateway::connect(TcpStream::connect(addr).await?);
+		tokio::spawn(async move {
+			let mut buf = vec![0; 64];
+			let _ = device.read(&mut buf).await.unwrap();
+			device.write_all(b"#OK\r\n").await.unwrap();
+		});
+		gw.ping().await?;
 
-		let (mut stream, _) = listener.accept().await.unwrap();
+		assert!(matches!(
+			StreamGateway::connect_serial("/dev/laurent-missing", 115200),
+			Err(Error::Io(_))
+		));
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_command_timeout() -> Result<()> {
+		let (client, device) = tokio::io::duplex(1024);
+		let opts = GatewayOptions::new().with_command_timeout(Duration::from_millis(100));
+		let gw = StreamGateway::connect_with(client, &opts)?;
 
+		// Answers every command, but takes its time with `RDR`.
 		tokio::spawn(async move {
-			let mut buf = vec![0; 64];
-			let _ = stream.read(&mut buf).await.unwrap();
-			stream.write_all(b"#RDR,3,1\r\n").await.unwrap();
+			let (rd, mut wr) = tokio::io::split(device);
+			let mut lines = BufReader::new(rd).lines();
+			while let Ok(Some(line)) = lines.next_line().await {
+				let reply = match line.as_str() {
+					"$KE,RDR,1" => {
+						tokio::time::sleep(Duration::from_millis(150)).await;
+						"#RDR,1,1\r\n"
+					}
+					_ => "#REL,OK\r\n",
+				};
+				wr.write_all(reply.as_bytes()).await.unwrap();
+			}
 		});
 
-		assert!(gw.relay_status(3).await?);
+		let started = Instant::now();
+		assert!(matches!(gw.relay_status(1).await, Err(Error::Timeout)));
+		assert!(started.elapsed() < Duration::from_secs(1));
+
+		// The late reply is dropped instead of answering the next command.
+		gw.relay(1, RelayAction::On, None).await?;
+		gw.relay(1, RelayAction::Off, None).await?;
 
 		Ok(())
 	}
 
 	#[tokio::test]
-	async fn gateway_line_signal() -> Result<()> {
-		let listener = TcpListener::bind("0.0.0.0:0").await?;
-		let addr = listener.local_addr()?;
+	async fn gateway_command_timeout_unanswered() -> Result<()> {
+		let opts = GatewayOptions::new().with_command_timeout(Duration::from_millis(50));
+		let (gw, _) = scripted_with(&opts, |cmd| match cmd {
+			"$KE,RDR,1" => String::new(),
+			"$KE,RDR,2" => "#RDR,2,1".into(),
+			_ => "#REL,OK".into(),
+		});
 
-		let gw = StreamGateway::connect(TcpStream::connect(addr).await?);
+		assert!(matches!(gw.relay_status(1).await, Err(Error::Timeout)));
 
-		let (mut stream, _) = listener.accept().await.unwrap();
+		// The reply that never came doesn't hold up the replies to later commands.
+		gw.relay(1, RelayAction::On, None).await?;
+		assert!(gw.relay_status(2).await?);
+		gw.relay(1, RelayAction::Off, None).await?;
 
-		tokio::spawn(async move {
-			let mut buf = vec![0; 64];
-			let _ = stream.read(&mut buf).await.unwrap();
-			stream.write_all(b"#RD,3,1\r\n").await.unwrap();
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_reconnects() -> Result<()> {
+		// Answers commands, passing each line on to the test.
+		async fn serve(stream: TcpStream, lines: mpsc::UnboundedSender<String>) {
+			let (rd, mut wr) = stream.into_split();
+			let mut rd = BufReader::new(rd).lines();
+			while let Ok(Some(line)) = rd.next_line().await {
+				let reply = match line.as_str() {
+					"$KE" => "#OK\r\n",
+					_ => "#MSG,SET,OK\r\n",
+				};
+				let _ = lines.send(line);
+				if wr.write_all(reply.as_bytes()).await.is_err() {
+					break;
+				}
+			}
+		}
+
+		let listener = TcpListener::bind("127.0.0.1:0").await?;
+		let addr = Arc::new(std::sync::Mutex::new(listener.local_addr()?));
+		let connect = {
+			let addr = addr.clone();
+			move || {
+				let addr = *addr.lock().unwrap();
+				async move { Ok(TcpStream::connect(addr).await?) }
+			}
+		};
+
+		let gw = StreamGateway::connect_reconnecting(connect, &GatewayOptions::new()).await?;
+		let mut state = gw.connection_state();
+		let (device, _) = listener.accept().await?;
+		let (lines, mut received) = mpsc::unbounded_channel();
+		let device = tokio::spawn(serve(device, lines));
+
+		gw.cfg_event(EventKind::Ein, true).await?;
+		assert_eq!(received.recv().await.unwrap(), "$KE,MSG,S,EIN,SET,ON");
+
+		// The module goes away, and comes back at another address.
+		drop(listener);
+		device.abort();
+		state
+			.wait_for(|state| *state == ConnectionState::Reconnecting)
+			.await
+			.unwrap();
+
+		let listener = TcpListener::bind("127.0.0.1:0").await?;
+		*addr.lock().unwrap() = listener.local_addr()?;
+		let (device, _) = listener.accept().await?;
+		let (lines, mut received) = mpsc::unbounded_channel();
+		tokio::spawn(serve(device, lines));
+
+		state
+			.wait_for(|state| *state == ConnectionState::Connected)
+			.await
+			.unwrap();
+		assert_eq!(received.recv().await.unwrap(), "$KE,MSG,S,EIN,SET,ON");
+		gw.ping().await?;
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_audit() -> Result<()> {
+		#[derive(Debug, Default)]
+		struct Trail(std::sync::Mutex<Vec<String>>);
+
+		impl Audit for Trail {
+			fn on_command(&self, record: &CommandRecord) {
+				self.0.lock().unwrap().push(record.command.clone());
+			}
+		}
+
+		let trail = Arc::new(Trail::default());
+		let opts = GatewayOptions::new().with_audit(trail.clone());
+		let (gw, _) = scripted_with(&opts, |cmd| match cmd {
+			"$KE,RDR,1" => "#RDR,1,1".into(),
+			_ => "#REL,OK".into(),
 		});
 
-		assert!(gw.line_signal(3).await?.is_high());
+		gw.relay(1, RelayAction::On, None).await?;
+		gw.relay_status(1).await?;
+		assert_eq!(*trail.0.lock().unwrap(), ["$KE,REL,1,1"]);
 
 		Ok(())
 	}
 
 	#[tokio::test]
-	async fn gateway_unexpected_message() -> Result<()> {
-		let listener = TcpListener::bind("0.0.0.0:0").await?;
-		let addr = listener.local_addr()?;
+	async fn gateway_change_password() -> Result<()> {
+		let (gw, received) = scripted(|cmd| match cmd {
+			"$KE,PSW,SET,old" => "#PSW,SET,OK".into(),
+			"$KE,PSW,SET,wrong" => "#PSW,SET,ERR".into(),
+			"$KE,PSW,NEW,new" => "#PSW,NEW,OK".into(),
+			_ => "#PSW,NEW,ERR".into(),
+		});
 
-		let gw = StreamGateway::connect(TcpStream::connect(addr).await?);
+		assert!(matches!(gw.authorize("wrong").await, Err(Error::Auth)));
+		assert!(matches!(
+			gw.change_password("other").await,
+			Err(Error::Auth)
+		));
+		gw.authorize("old").await?;
+		gw.change_password("new").await?;
+		assert_eq!(gw.session.lock().unwrap().password.as_deref(), Some("new"));
+		assert_eq!(received.lock().unwrap()[3], "$KE,PSW,NEW,new");
 
-		let (mut stream, _) = listener.accept().await.unwrap();
+		Ok(())
+	}
 
-		tokio::spawn(async move {
-			let mut buf = vec![0; 64];
-			let _ = stream.read(&mut buf).await.unwrap();
-			stream.write_all(b"#RDR,3,1\r\n").await.unwrap();
+	#[tokio::test]
+	async fn gateway_input_frequency() -> Result<()> {
+		let (gw, _) = scripted(|cmd| match cmd {
+			"$KE,FRQ,1" => "#FRQ,1,123.5".into(),
+			"$KE,FRQ,2" => "#FRQ,2,OFF".into(),
+			_ => "#ERR".into(),
 		});
 
-		assert!(matches!(gw.relay_status(1).await, Err(Error::UnexpectedMessage)));
+		assert_eq!(gw.input_frequency(1).await?, 123.5);
+		assert!(matches!(
+			gw.input_frequency(2).await,
+			Err(Error::InvalidPayload(_))
+		));
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn gateway_write_all_relays() -> Result<()> {
+		let (client, mut device) = tokio::io::duplex(1024);
+		let gw = StreamGateway::connect(client);
+
+		let write = tokio::spawn(async move { gw.write_all_relays(0b1010).await });
+		let mut buf = vec![0; 64];
+		let n = device.read(&mut buf).await?;
+		assert_eq!(&buf[..n], b"$KE,WR,ALL,10\r\n");
+		device.write_all(b"#WR,ALL,OK\r\n").await?;
+		write.await.unwrap()?;
 
 		Ok(())
 	}
@@ -1,11 +1,35 @@
+mod audit;
+mod board;
+mod caps;
+mod cmd;
 mod codec;
 mod err;
 mod event;
+#[cfg(any(test, feature = "test-util"))]
+mod fake;
 mod gw;
 mod lio;
+#[cfg(any(test, feature = "test-util"))]
+mod mock;
+mod opts;
+mod pool;
+mod retry;
+#[cfg(test)]
+mod testing;
 mod utils;
 
+pub use audit::*;
+pub use board::*;
+pub use caps::*;
+pub use cmd::*;
 pub use err::*;
 pub use event::*;
+#[cfg(any(test, feature = "test-util"))]
+pub use fake::*;
 pub use gw::*;
 pub use lio::*;
+#[cfg(any(test, feature = "test-util"))]
+pub use mock::*;
+pub use opts::*;
+pub use pool::*;
+pub use retry::*;
@@ -1,16 +1,87 @@
+use crate::recv_event;
+use crate::ConnectionState;
 use crate::Error;
 use crate::Event;
 use crate::EventReceiver;
 use crate::Gateway;
 use crate::Result;
+use crate::RetryPolicy;
+use async_trait::async_trait;
+use futures::Stream;
+use std::fmt::Debug;
 use std::fmt::Display;
 use std::fmt::Formatter;
+use std::ops::BitAnd;
+use std::ops::BitOr;
+use std::ops::BitXor;
+use std::ops::Not;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
+use std::time::Instant;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::watch;
 use tokio::sync::Mutex;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// Number of relays on a Laurent-2.
+pub const RELAY_COUNT: u32 = 4;
+
+/// Number of ADC channels on a Laurent-2.
+pub const ADC_COUNT: u32 = 2;
+
+/// Number of digital inputs on a Laurent-2.
+pub const INPUT_COUNT: u32 = 6;
+
+/// Number of logic-level outputs on a Laurent-2.
+pub const OUTPUT_COUNT: u32 = 12;
+
+/// Most pulses a single [`Gateway::pulse_output`] burst may contain.
+pub const PULSE_MAX_COUNT: u32 = 65535;
+
+/// Highest pulse frequency in Hz accepted by [`Gateway::pulse_output`].
+pub const PULSE_MAX_FREQ_HZ: u32 = 1000;
+
+/// Longest click delay in seconds accepted by [`Gateway::relay`].
+pub const CLICK_MAX_SECS: u32 = 65535;
+
+/// Number of PWM outputs on a Laurent-2.
+pub const PWM_COUNT: u32 = 1;
+
+/// Highest PWM duty cycle in percent accepted by [`Gateway::set_pwm`].
+pub const PWM_DUTY_MAX: u8 = 100;
+
+pub(crate) fn check_relay(relay: u32) -> Result<()> {
+	check_index("relay", relay, RELAY_COUNT)
+}
+
+pub(crate) fn check_input(line: u32) -> Result<()> {
+	check_index("input line", line, INPUT_COUNT)
+}
+
+pub(crate) fn check_output(channel: u32) -> Result<()> {
+	check_index("output", channel, OUTPUT_COUNT)
+}
+
+pub(crate) fn check_pwm(channel: u32) -> Result<()> {
+	check_index("PWM output", channel, PWM_COUNT)
+}
+
+pub(crate) fn check_pulse(count: u32, freq_hz: u32) -> Result<()> {
+	check_index("pulse count", count, PULSE_MAX_COUNT)?;
+	check_index("pulse frequency", freq_hz, PULSE_MAX_FREQ_HZ)
+}
+
+fn check_index(what: &str, index: u32, count: u32) -> Result<()> {
+	if (1..=count).contains(&index) {
+		Ok(())
+	} else {
+		Err(Error::InvalidPayload(format!(
+			"The {what} index must be between 1 and {count}. Received: `{index}`"
+		)))
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Signal {
 	High,
 	Low,
@@ -25,6 +96,29 @@ impl Signal {
 		matches!(self, Self::Low)
 	}
 
+	/// The opposite level, e.g. for an active-low input. Same as `!signal`, without
+	/// importing [`Not`].
+	#[allow(clippy::should_implement_trait)]
+	pub fn not(self) -> Self {
+		Not::not(self)
+	}
+
+	/// Whether every signal is high. True for an empty set, like [`Iterator::all`].
+	pub fn all_high<I>(signals: I) -> bool
+	where
+		I: IntoIterator<Item = Signal>,
+	{
+		signals.into_iter().all(|signal| signal.is_high())
+	}
+
+	/// Whether at least one signal is high.
+	pub fn any_high<I>(signals: I) -> bool
+	where
+		I: IntoIterator<Item = Signal>,
+	{
+		signals.into_iter().any(|signal| signal.is_high())
+	}
+
 	pub fn from_bool(value: bool) -> Self {
 		match value {
 			true => Self::High,
@@ -52,6 +146,39 @@ impl Display for Signal {
 	}
 }
 
+impl BitAnd for Signal {
+	type Output = Self;
+
+	fn bitand(self, rhs: Self) -> Self {
+		Self::from_bool(self.is_high() && rhs.is_high())
+	}
+}
+
+impl BitOr for Signal {
+	type Output = Self;
+
+	fn bitor(self, rhs: Self) -> Self {
+		Self::from_bool(self.is_high() || rhs.is_high())
+	}
+}
+
+impl BitXor for Signal {
+	type Output = Self;
+
+	fn bitxor(self, rhs: Self) -> Self {
+		Self::from_bool(self.is_high() != rhs.is_high())
+	}
+}
+
+/// Inverts the level, e.g. for an active-low input.
+impl Not for Signal {
+	type Output = Self;
+
+	fn not(self) -> Self {
+		Self::from_bool(self.is_low())
+	}
+}
+
 impl From<bool> for Signal {
 	fn from(value: bool) -> Self {
 		Self::from_bool(value)
@@ -66,7 +193,65 @@ impl FromStr for Signal {
 	}
 }
 
-#[derive(Debug)]
+/// A frame captured by an input in advanced mode, such as a Wiegand card or keypad read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdvancedData {
+	/// Number of bits in the frame.
+	pub bits: u32,
+	/// The frame as read, first bit received in the most significant position.
+	pub raw: u64,
+}
+
+impl AdvancedData {
+	/// Parses the `<bits>,<hex>` pair the module reports for advanced input reads.
+	pub fn parse(bits: &str, raw: &str) -> Result<Self> {
+		let bits = bits.parse()?;
+		if bits > 64 {
+			return Err(Error::InvalidPayload(format!(
+				"Advanced input frames are at most 64 bits long. Received: `{bits}`"
+			)));
+		}
+
+		Ok(Self {
+			bits,
+			raw: u64::from_str_radix(raw, 16)?,
+		})
+	}
+
+	/// Facility code and card number of a standard 26-bit Wiegand frame.
+	///
+	/// Parity bits are not checked.
+	pub fn wiegand26(&self) -> Option<(u8, u16)> {
+		if self.bits != 26 {
+			return None;
+		}
+
+		let facility = (self.raw >> 17) as u8;
+		let card = (self.raw >> 1) as u16;
+		Some((facility, card))
+	}
+}
+
+/// An input a relay follows on its own, without the host being involved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RelayLink {
+	pub line: u32,
+	/// Whether the relay is on while the input is low rather than high.
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub invert: bool,
+}
+
+/// A sensor found on the 1-Wire bus.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OneWireSensor {
+	/// Index the module uses to address the sensor in 1-Wire commands.
+	pub index: u32,
+	/// 64-bit ROM code burned into the sensor.
+	pub rom: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RelayAction {
 	On,
 	Off,
@@ -83,12 +268,91 @@ impl Display for RelayAction {
 	}
 }
 
-#[derive(Debug)]
+impl RelayAction {
+	/// Parses the wire form written by `Display` or the action's name, ignoring case.
+	pub fn parse(value: &str) -> Result<Self> {
+		match value.to_ascii_lowercase().as_str() {
+			"1" | "on" => Ok(Self::On),
+			"0" | "off" => Ok(Self::Off),
+			"2" | "toggle" => Ok(Self::Toggle),
+			_ => Err(Error::InvalidPayload(format!(
+				"A relay action is `1`/`on`, `0`/`off` or `2`/`toggle`. Received: `{value}`"
+			))),
+		}
+	}
+}
+
+impl FromStr for RelayAction {
+	type Err = Error;
+
+	fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+		Self::parse(s)
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ClickDelay {
 	Millis100(u32),
 	Seconds(u32),
 }
 
+impl ClickDelay {
+	/// Parses the wire form written by `Display`: `.N` for tenths of a second, `N` for
+	/// seconds.
+	pub fn parse(value: &str) -> Result<Self> {
+		let invalid = || {
+			Error::InvalidPayload(format!(
+				"A click delay is written as `.N` (tenths) or `N` (seconds). Received: `{value}`"
+			))
+		};
+
+		match value.strip_prefix('.') {
+			Some(m100) => m100.parse().map(Self::Millis100).map_err(|_| invalid()),
+			None => value.parse().map(Self::Seconds).map_err(|_| invalid()),
+		}
+	}
+
+	/// Converts a duration into the delay the firmware understands: whole seconds as
+	/// [`Seconds`](Self::Seconds), anything else rounded to tenths of a second.
+	///
+	/// Fails for a duration that rounds to zero or is longer than [`CLICK_MAX_SECS`].
+	pub fn from_duration(duration: Duration) -> Result<Self> {
+		let millis = duration.as_millis();
+		let delay = if millis.is_multiple_of(1000) {
+			u32::try_from(millis / 1000).ok().map(Self::Seconds)
+		} else {
+			u32::try_from((millis + 50) / 100).ok().map(Self::Millis100)
+		};
+
+		match delay {
+			Some(delay)
+				if delay.duration() > Duration::ZERO
+					&& delay.duration() <= Duration::from_secs(CLICK_MAX_SECS.into()) =>
+			{
+				Ok(delay)
+			}
+			_ => Err(Error::InvalidPayload(format!(
+				"A click delay must be between 100 ms and {CLICK_MAX_SECS} s. Received: `{duration:?}`"
+			))),
+		}
+	}
+
+	pub fn duration(&self) -> Duration {
+		match *self {
+			Self::Millis100(m100) => Duration::from_millis(u64::from(m100) * 100),
+			Self::Seconds(secs) => Duration::from_secs(u64::from(secs)),
+		}
+	}
+}
+
+impl FromStr for ClickDelay {
+	type Err = Error;
+
+	fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+		Self::parse(s)
+	}
+}
+
 impl Display for ClickDelay {
 	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
 		match self {
@@ -98,35 +362,174 @@ impl Display for ClickDelay {
 	}
 }
 
+/// What the client has observed about a relay.
+#[derive(Debug, Default)]
+pub(crate) struct RelayRecord {
+	/// Last state seen, from a command or an event.
+	state: Option<bool>,
+	last_change: Option<Instant>,
+	cycles: u64,
+	/// Commands confirmed, for the average latency.
+	confirmed: u32,
+	last_latency: Option<Duration>,
+	total_latency: Duration,
+}
+
+impl RelayRecord {
+	/// Records a command the module confirmed after `latency`.
+	fn confirmed(&mut self, action: RelayAction, latency: Duration) {
+		self.confirmed += 1;
+		self.last_latency = Some(latency);
+		self.total_latency += latency;
+
+		match action {
+			RelayAction::On => self.switched(true),
+			RelayAction::Off => self.switched(false),
+			RelayAction::Toggle => match self.state {
+				Some(on) => self.switched(!on),
+				// A toggle always switches, even if nobody knows to which state.
+				None => self.change(),
+			},
+		}
+	}
+
+	/// Records that the relay is in state `on`, counting a change if it wasn't before.
+	pub(crate) fn switched(&mut self, on: bool) {
+		if self.state != Some(on) {
+			self.state = Some(on);
+			self.change();
+		}
+	}
+
+	fn change(&mut self) {
+		self.last_change = Some(Instant::now());
+		self.cycles += 1;
+	}
+
+	pub(crate) fn cycles(&self) -> u64 {
+		self.cycles
+	}
+
+	pub(crate) fn since_change(&self) -> Option<Duration> {
+		self.last_change.map(|at| at.elapsed())
+	}
+}
+
 #[derive(Debug, Clone)]
 pub struct Relay {
 	line: u32,
 	gw: Arc<dyn Gateway + Send + Sync + 'static>,
+	record: Arc<std::sync::Mutex<RelayRecord>>,
+	retry: RetryPolicy,
 }
 
 impl Relay {
 	pub fn new(gw: Arc<dyn Gateway + Send + Sync + 'static>, line: u32) -> Self {
-		Self { gw, line }
+		Self::with_record(gw, line, Default::default())
+	}
+
+	pub(crate) fn with_record(
+		gw: Arc<dyn Gateway + Send + Sync + 'static>,
+		line: u32,
+		record: Arc<std::sync::Mutex<RelayRecord>>,
+	) -> Self {
+		Self {
+			gw,
+			line,
+			record,
+			retry: RetryPolicy::none(),
+		}
+	}
+
+	/// Retries commands that fail with a transient error. [`toggle`](Self::toggle) is never
+	/// retried, since a lost reply doesn't tell whether the relay already switched.
+	pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+		self.retry = retry;
+		self
 	}
 
 	pub async fn status(&self) -> Result<bool> {
-		self.gw.relay_status(self.line).await
+		self.retry.run(|| self.gw.relay_status(self.line)).await
+	}
+
+	/// Like [`status`](Self::status), with an energized relay read as [`Signal::High`].
+	pub async fn signal(&self) -> Result<Signal> {
+		self.status().await.map(Signal::from_bool)
 	}
 
 	pub async fn on(&self) -> Result<()> {
-		self.gw.relay(self.line, RelayAction::On, None).await
+		self.command(RelayAction::On, None).await
 	}
 
 	pub async fn off(&self) -> Result<()> {
-		self.gw.relay(self.line, RelayAction::Off, None).await
+		self.command(RelayAction::Off, None).await
+	}
+
+	/// Turns the relay on for [`Signal::High`] (or `true`) and off otherwise.
+	pub async fn set<S>(&self, level: S) -> Result<()>
+	where
+		S: Into<Signal>,
+	{
+		match level.into() {
+			Signal::High => self.on().await,
+			Signal::Low => self.off().await,
+		}
 	}
 
 	pub async fn toggle(&self) -> Result<()> {
-		self.gw.relay(self.line, RelayAction::Toggle, None).await
+		let latency = self.timed(RelayAction::Toggle, None).await?;
+		self
+			.record
+			.lock()
+			.unwrap()
+			.confirmed(RelayAction::Toggle, latency);
+		Ok(())
+	}
+
+	/// Toggles the relay and reads back the state it ended up in.
+	///
+	/// The state is read with a second command once the toggle is acknowledged. Anything
+	/// that switches the relay in between, such as another client, an input link or a click
+	/// timer running out, shows in the result as well.
+	pub async fn toggle_and_read(&self) -> Result<bool> {
+		self.toggle().await?;
+		self.status().await
+	}
+
+	/// Reads the relay and explicitly switches it to the opposite state, returning the new
+	/// state. Unlike [`toggle`](Self::toggle) this doesn't depend on the device's idea of
+	/// the current state, at the cost of a round trip.
+	pub async fn toggle_safe(&self) -> Result<bool> {
+		let on = !self.status().await?;
+		self.set(on).await?;
+		Ok(on)
 	}
 
 	pub async fn click(&self, delay: ClickDelay) -> Result<()> {
-		self.gw.relay(self.line, RelayAction::On, Some(delay)).await
+		self.command(RelayAction::On, Some(delay)).await
+	}
+
+	/// Like [`click`](Self::click), with the delay converted by
+	/// [`ClickDelay::from_duration`].
+	pub async fn click_for(&self, duration: Duration) -> Result<()> {
+		self.click(ClickDelay::from_duration(duration)?).await
+	}
+
+	/// Time since this client last saw the relay change, if it ever did.
+	pub fn last_change(&self) -> Option<Duration> {
+		self.record.lock().unwrap().since_change()
+	}
+
+	/// How long the module took to confirm the last successful command.
+	pub fn last_latency(&self) -> Option<Duration> {
+		self.record.lock().unwrap().last_latency
+	}
+
+	/// Average time the module took to confirm a successful command.
+	pub fn avg_latency(&self) -> Option<Duration> {
+		let record = self.record.lock().unwrap();
+		let confirmed = Some(record.confirmed).filter(|&confirmed| confirmed > 0)?;
+		Some(record.total_latency / confirmed)
 	}
 
 	pub async fn programmatic_click(&self, duration: Duration) -> Result<()> {
@@ -134,34 +537,1189 @@ impl Relay {
 		tokio::time::sleep(duration).await;
 		self.off().await
 	}
+
+	async fn command(&self, action: RelayAction, delay: Option<ClickDelay>) -> Result<()> {
+		let latency = self.retry.run(|| self.timed(action, delay)).await?;
+		self.record.lock().unwrap().confirmed(action, latency);
+		Ok(())
+	}
+
+	/// Sends a command and measures how long the reply took.
+	async fn timed(&self, action: RelayAction, delay: Option<ClickDelay>) -> Result<Duration> {
+		let sent = Instant::now();
+		self.gw.relay(self.line, action, delay).await?;
+		Ok(sent.elapsed())
+	}
+}
+
+/// All relays of a module, switched together.
+#[derive(Debug, Clone)]
+pub struct RelayBank {
+	gw: Arc<dyn Gateway + Send + Sync + 'static>,
+}
+
+impl RelayBank {
+	pub fn new(gw: Arc<dyn Gateway + Send + Sync + 'static>) -> Self {
+		Self { gw }
+	}
+
+	/// Turns on the relays whose bit is set in `mask`, relay 1 being bit 0, and the others
+	/// off, in a single command.
+	pub async fn set_all(&self, mask: u32) -> Result<()> {
+		self.gw.write_all_relays(mask).await
+	}
+}
+
+/// Relays of which at most one is on at a time, like the buttons of a source selector.
+#[derive(Debug, Clone)]
+pub struct RadioGroup {
+	relays: Vec<u32>,
+	gw: Arc<dyn Gateway + Send + Sync + 'static>,
+}
+
+impl RadioGroup {
+	/// Fails with [`Error::InvalidPayload`] if a relay number is out of range or listed
+	/// twice.
+	pub fn new(gw: Arc<dyn Gateway + Send + Sync + 'static>, relays: Vec<u32>) -> Result<Self> {
+		for (i, relay) in relays.iter().enumerate() {
+			check_relay(*relay)?;
+			if relays[..i].contains(relay) {
+				return Err(Error::InvalidPayload(format!(
+					"Relays of a group must be distinct. Received relay `{relay}` twice"
+				)));
+			}
+		}
+
+		Ok(Self { gw, relays })
+	}
+
+	/// Turns on the relay at `index` within the group and the others off, with a single
+	/// masked write so there is never a moment where two of them are on.
+	pub async fn select(&self, index: usize) -> Result<()> {
+		let Some(relay) = self.relays.get(index) else {
+			return Err(Error::InvalidPayload(format!(
+				"The group has {} relays. Received index: `{index}`",
+				self.relays.len()
+			)));
+		};
+
+		self.gw.write_relays(self.mask(), 1 << (relay - 1)).await
+	}
+
+	/// Turns every relay of the group off.
+	pub async fn clear(&self) -> Result<()> {
+		self.gw.write_relays(self.mask(), 0).await
+	}
+
+	fn mask(&self) -> u32 {
+		self
+			.relays
+			.iter()
+			.fold(0, |mask, relay| mask | 1 << (relay - 1))
+	}
+}
+
+/// A 1-Wire temperature sensor that keeps track of its extremes.
+///
+/// Firmware that records extremes itself is asked for them. Otherwise they are tracked
+/// from the readings passed to [`observe`](Self::observe), so they only cover what this
+/// client has seen.
+#[derive(Debug, Clone)]
+pub struct TempSensor {
+	sensor: u32,
+	gw: Arc<dyn Gateway + Send + Sync + 'static>,
+	extremes: Arc<std::sync::Mutex<Option<(f32, f32)>>>,
+}
+
+impl TempSensor {
+	pub fn new(gw: Arc<dyn Gateway + Send + Sync + 'static>, sensor: u32) -> Self {
+		Self {
+			gw,
+			sensor,
+			extremes: Default::default(),
+		}
+	}
+
+	/// Reads the sensor and records the reading, see [`observe`](Self::observe).
+	pub async fn read(&self) -> Result<f32> {
+		let celsius = self.gw.read_temperature(self.sensor).await?;
+		self.observe(celsius);
+		Ok(celsius)
+	}
+
+	/// Records a reading taken by polling or received as an event.
+	pub fn observe(&self, celsius: f32) {
+		let mut extremes = self.extremes.lock().unwrap();
+		*extremes = Some(match *extremes {
+			Some((min, max)) => (min.min(celsius), max.max(celsius)),
+			None => (celsius, celsius),
+		});
+	}
+
+	/// Lowest and highest temperature since the last reset, or `None` if nothing has been
+	/// observed yet.
+	pub async fn extremes(&self) -> Result<Option<(f32, f32)>> {
+		match self.gw.temperature_extremes(self.sensor).await {
+			Ok(extremes) => Ok(Some(extremes)),
+			Err(Error::Unsupported | Error::SyntaxError(_)) => Ok(*self.extremes.lock().unwrap()),
+			Err(err) => Err(err),
+		}
+	}
+
+	pub async fn reset_extremes(&self) -> Result<()> {
+		match self.gw.reset_temperature_extremes(self.sensor).await {
+			Ok(()) | Err(Error::Unsupported | Error::SyntaxError(_)) => (),
+			Err(err) => return Err(err),
+		}
+
+		*self.extremes.lock().unwrap() = None;
+		Ok(())
+	}
+}
+
+/// Running total of an input's pulse counter across the device counter's rollovers.
+///
+/// The device counts modulo `modulus` and sends [`Event::CounterOverflow`] when it wraps.
+/// Readings and events must be passed in the order they arrived for the total to stay
+/// exact.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PulseCounter {
+	line: u32,
+	modulus: u64,
+	base: u64,
+	last: u64,
+}
+
+impl PulseCounter {
+	pub fn new(line: u32, modulus: u64) -> Self {
+		Self {
+			line,
+			modulus,
+			base: 0,
+			last: 0,
+		}
+	}
+
+	/// Records a raw reading of the device counter and returns the running total.
+	pub fn observe(&mut self, raw: u64) -> u64 {
+		self.last = raw;
+		self.total()
+	}
+
+	/// Accounts for a rollover if `event` reports one for this line.
+	pub fn handle(&mut self, event: &Event) {
+		if let Event::CounterOverflow { line } = event {
+			if *line == self.line {
+				self.base += self.modulus;
+			}
+		}
+	}
+
+	pub fn total(&self) -> u64 {
+		self.base + self.last
+	}
+}
+
+#[derive(Debug)]
+struct LineWatch {
+	sub: EventReceiver,
+	/// Last level reported to the caller.
+	last: Option<Signal>,
+	/// State of a gateway that reconnects behind the same event channel.
+	state: Option<watch::Receiver<ConnectionState>>,
+	/// The level still has to be read after resubscribing or reconnecting. Kept here rather
+	/// than in `wait_signal` so that a call cancelled halfway through is finished by the
+	/// next one.
+	resync: bool,
+}
+
+impl LineWatch {
+	/// The next event, or `None` once the gateway has connected again, with `resync` set.
+	async fn recv(&mut self) -> Option<std::result::Result<Event, RecvError>> {
+		let Some(state) = &mut self.state else {
+			return Some(self.sub.recv().await);
+		};
+
+		tokio::select! {
+			biased;
+
+			event = self.sub.recv() => Some(event),
+			changed = state.changed() => {
+				match changed {
+					Ok(()) => self.resync |= *state.borrow_and_update() == ConnectionState::Connected,
+					// The gateway stopped for good; its event channel closes too.
+					Err(_) => self.state = None,
+				}
+				None
+			}
+		}
+	}
 }
 
 #[derive(Debug)]
 pub struct InputLine {
 	line: u32,
-	sub: Mutex<EventReceiver>,
+	watch: Mutex<LineWatch>,
 	gw: Arc<dyn Gateway + Send + Sync + 'static>,
+	retry: RetryPolicy,
 }
 
 impl InputLine {
 	pub fn new(gw: Arc<dyn Gateway + Send + Sync + 'static>, line: u32) -> Self {
+		let watch = LineWatch {
+			sub: gw.subscibe(),
+			last: None,
+			state: gw.watch_connection(),
+			resync: false,
+		};
+
 		Self {
-			sub: Mutex::new(gw.subscibe()),
+			watch: Mutex::new(watch),
 			gw,
 			line,
+			retry: RetryPolicy::none(),
 		}
 	}
 
+	/// Retries reads that fail with a transient error.
+	pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+		self.retry = retry;
+		self
+	}
+
 	pub async fn read_signal(&self) -> Result<Signal> {
-		self.gw.line_signal(self.line).await
+		self.retry.run(|| self.gw.line_signal(self.line)).await
 	}
 
+	/// Pulses counted on this line, see [`Gateway::read_counter`].
+	pub async fn count(&self) -> Result<u32> {
+		self.retry.run(|| self.gw.read_counter(self.line)).await
+	}
+
+	pub async fn reset_count(&self) -> Result<()> {
+		self.gw.reset_counter(self.line).await
+	}
+
+	/// Waits for the next `EIN` event on this line.
+	///
+	/// When the gateway reconnects, the line reads the current level again, subscribing
+	/// anew first if the event channel closed. Events keep coming across a dropped
+	/// connection, and an edge missed while disconnected is returned right away. Returns
+	/// [`Error::Closed`] if the gateway can't be reached anymore, so callers can tell a dead
+	/// connection apart from other failures; a failed read of the level is passed on as is.
+	///
+	/// A caller that falls so far behind that the oldest events are dropped gets
+	/// [`Error::EventsLagged`] with the number it missed. The next call reads the current
+	/// level first, so a missed edge is still returned.
+	///
+	/// # Cancel safety
+	///
+	/// This method is cancel-safe, so it can be used as a `tokio::select!` branch. Events
+	/// stay queued in the subscription until they are returned, and a resync interrupted by
+	/// cancellation is picked up by the next call.
 	pub async fn wait_signal(&self) -> Result<Signal> {
+		let mut watch = self.watch.lock().await;
+		let mut resubscribed = false;
+
 		loop {
-			match self.sub.lock().await.recv().await? {
-				Event::Ein { line, signal } if line == self.line => return Ok(signal),
-				_ => (),
+			if watch.resync {
+				let signal = self
+					.gw
+					.line_signal(self.line)
+					.await
+					.map_err(|err| match err {
+						Error::Send => Error::Closed,
+						err => err,
+					})?;
+				watch.resync = false;
+
+				let missed = watch.last.is_some_and(|last| last != signal);
+				watch.last = Some(signal);
+				if missed {
+					return Ok(signal);
+				}
 			}
+
+			let Some(event) = watch.recv().await else {
+				continue;
+			};
+
+			match event {
+				Ok(Event::Ein { line, signal }) if line == self.line => {
+					watch.last = Some(signal);
+					return Ok(signal);
+				}
+				Ok(_) => resubscribed = false,
+				Err(RecvError::Closed) if !resubscribed => {
+					watch.sub = self.gw.subscibe();
+					watch.resync = true;
+					resubscribed = true;
+				}
+				Err(RecvError::Closed) => return Err(Error::Closed),
+				Err(RecvError::Lagged(missed)) => {
+					watch.resync = true;
+					return Err(Error::EventsLagged(missed));
+				}
+			}
+		}
+	}
+
+	/// Like [`wait_signal`](Self::wait_signal), for a bouncing contact: after an event,
+	/// waits until the line has been quiet for `window` and returns the level it settled
+	/// on. Every event during the window starts it over.
+	///
+	/// Cancelling it during the window loses the levels seen so far; the next call waits for
+	/// a new event.
+	pub async fn wait_signal_debounced(&self, window: Duration) -> Result<Signal> {
+		let mut signal = self.wait_signal().await?;
+		while let Ok(next) = tokio::time::timeout(window, self.wait_signal()).await {
+			signal = next?;
+		}
+
+		Ok(signal)
+	}
+
+	/// Waits for the line to go from low to high. Repeated reports of the same level are
+	/// skipped. If no level has been seen yet, the current one is read first.
+	///
+	/// Cancel-safe like [`wait_signal`](Self::wait_signal).
+	pub async fn wait_rising(&self) -> Result<()> {
+		self.wait_edge(Signal::High).await
+	}
+
+	/// Waits for the line to go from high to low, see [`wait_rising`](Self::wait_rising).
+	pub async fn wait_falling(&self) -> Result<()> {
+		self.wait_edge(Signal::Low).await
+	}
+
+	async fn wait_edge(&self, to: Signal) -> Result<()> {
+		let mut prev = self.watch.lock().await.last;
+		if prev.is_none() {
+			let signal = self.read_signal().await?;
+			prev = Some(*self.watch.lock().await.last.get_or_insert(signal));
+		}
+
+		loop {
+			let signal = self.wait_signal().await?;
+			if signal == to && prev != Some(to) {
+				return Ok(());
+			}
+			prev = Some(signal);
+		}
+	}
+}
+
+/// An addressable point read and written as a [`Signal`], whatever kind of line it is.
+///
+/// Read-only points answer [`write`](Self::write) with [`Error::Unsupported`].
+#[async_trait]
+pub trait IoPoint: Debug + Send + Sync {
+	async fn read(&self) -> Result<Signal>;
+
+	async fn write(&self, _signal: Signal) -> Result<()> {
+		Err(Error::Unsupported)
+	}
+}
+
+#[async_trait]
+impl IoPoint for Relay {
+	async fn read(&self) -> Result<Signal> {
+		self.signal().await
+	}
+
+	async fn write(&self, signal: Signal) -> Result<()> {
+		self.set(signal).await
+	}
+}
+
+#[async_trait]
+impl IoPoint for InputLine {
+	async fn read(&self) -> Result<Signal> {
+		self.read_signal().await
+	}
+}
+
+/// A digital output channel.
+#[derive(Debug, Clone)]
+pub struct OutputChannel {
+	channel: u32,
+	gw: Arc<dyn Gateway + Send + Sync + 'static>,
+}
+
+impl OutputChannel {
+	pub fn new(gw: Arc<dyn Gateway + Send + Sync + 'static>, channel: u32) -> Self {
+		Self { gw, channel }
+	}
+
+	pub async fn level(&self) -> Result<Signal> {
+		self.gw.output(self.channel).await
+	}
+
+	pub async fn set_level(&self, level: Signal) -> Result<()> {
+		self.gw.set_output(self.channel, level).await
+	}
+}
+
+#[async_trait]
+impl IoPoint for OutputChannel {
+	async fn read(&self) -> Result<Signal> {
+		self.level().await
+	}
+
+	async fn write(&self, signal: Signal) -> Result<()> {
+		self.set_level(signal).await
+	}
+}
+
+/// An ADC channel read as a digital line: [`Signal::High`] at or above `threshold`.
+#[derive(Debug, Clone)]
+pub struct AnalogInput {
+	channel: u32,
+	threshold: u16,
+	gw: Arc<dyn Gateway + Send + Sync + 'static>,
+}
+
+impl AnalogInput {
+	pub fn new(gw: Arc<dyn Gateway + Send + Sync + 'static>, channel: u32, threshold: u16) -> Self {
+		Self {
+			gw,
+			channel,
+			threshold,
 		}
 	}
+
+	pub async fn value(&self) -> Result<u16> {
+		self.gw.read_adc(self.channel).await
+	}
+
+	/// Values of the channel as the module reports them, with [`EventKind::Advc`] events
+	/// or sampling turned on, from the moment of the call.
+	///
+	/// A subscriber that falls behind gets [`Error::EventsLagged`] with the number of events
+	/// it missed, and goes on with the oldest one still queued; the stream ends when the
+	/// gateway is gone.
+	///
+	/// [`EventKind::Advc`]: crate::EventKind::Advc
+	pub fn changes(&self) -> impl Stream<Item = Result<u16>> + Send + 'static {
+		let channel = self.channel;
+		futures::stream::unfold(self.gw.subscibe(), move |mut sub| async move {
+			loop {
+				match recv_event(&mut sub).await {
+					Ok(Event::Adc {
+						channel: cid,
+						value,
+					}) if cid == channel => return Some((Ok(value), sub)),
+					Ok(_) => (),
+					Err(Error::Closed) => return None,
+					Err(err) => return Some((Err(err), sub)),
+				}
+			}
+		})
+	}
+}
+
+#[async_trait]
+impl IoPoint for AnalogInput {
+	async fn read(&self) -> Result<Signal> {
+		let value = self.value().await?;
+		Ok(Signal::from_bool(value >= self.threshold))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::scripted;
+	use crate::EventKind;
+	use crate::FakeDevice;
+	use crate::GatewayOptions;
+	use crate::StreamGateway;
+	use std::sync::atomic::AtomicBool;
+	use std::sync::atomic::AtomicU32;
+	use std::sync::atomic::Ordering;
+	use tokio::sync::broadcast;
+
+	#[test]
+	fn signal_combinators() {
+		use Signal::*;
+
+		assert_eq!(High & High, High);
+		assert_eq!(High & Low, Low);
+		assert_eq!(Low | High, High);
+		assert_eq!(Low | Low, Low);
+		assert_eq!(High ^ High, Low);
+		assert_eq!(High ^ Low, High);
+		assert_eq!(!High, Low);
+		assert_eq!(Low.not(), High);
+		assert_eq!(Signal::not(High), Low);
+		assert!(High.is_high() && !High.is_low());
+		assert!(Low.is_low() && !Low.is_high());
+
+		assert!(Signal::all_high([High, High, High]));
+		assert!(!Signal::all_high([High, Low, High]));
+		assert!(Signal::all_high([]));
+		assert!(Signal::any_high([Low, Low, High]));
+		assert!(!Signal::any_high([Low, Low]));
+		assert!(!Signal::any_high([]));
+	}
+
+	#[test]
+	fn relay_action_from_str() {
+		for action in [RelayAction::On, RelayAction::Off, RelayAction::Toggle] {
+			assert_eq!(action.to_string().parse::<RelayAction>().unwrap(), action);
+		}
+		assert_eq!("ON".parse::<RelayAction>().unwrap(), RelayAction::On);
+		assert_eq!(
+			"Toggle".parse::<RelayAction>().unwrap(),
+			RelayAction::Toggle
+		);
+		assert!(matches!(
+			"3".parse::<RelayAction>(),
+			Err(Error::InvalidPayload(_))
+		));
+	}
+
+	#[test]
+	fn click_delay_from_str() {
+		assert_eq!(
+			".5".parse::<ClickDelay>().unwrap(),
+			ClickDelay::Millis100(5)
+		);
+		assert_eq!("10".parse::<ClickDelay>().unwrap(), ClickDelay::Seconds(10));
+		assert!(matches!(
+			"abc".parse::<ClickDelay>(),
+			Err(Error::InvalidPayload(_))
+		));
+		assert!(matches!(
+			".".parse::<ClickDelay>(),
+			Err(Error::InvalidPayload(_))
+		));
+
+		for delay in [ClickDelay::Millis100(3), ClickDelay::Seconds(7)] {
+			assert_eq!(delay.to_string().parse::<ClickDelay>().unwrap(), delay);
+		}
+	}
+
+	#[test]
+	fn advanced_data_wiegand26() {
+		// Facility 18, card 4660, with both parity bits set.
+		let data = AdvancedData::parse("26", "2242469").unwrap();
+		assert_eq!(data.wiegand26(), Some((18, 4660)));
+
+		let data = AdvancedData::parse("34", "2242469").unwrap();
+		assert_eq!(data.wiegand26(), None);
+	}
+
+	#[tokio::test]
+	async fn wait_signal_returns_closed_on_disconnect() {
+		let (client, device) = tokio::io::duplex(64);
+		let input = InputLine::new(Arc::new(StreamGateway::connect(client)), 1);
+
+		let wait = tokio::spawn(async move { input.wait_signal().await });
+		tokio::task::yield_now().await;
+		drop(device);
+
+		assert!(matches!(wait.await.unwrap(), Err(Error::Closed)));
+	}
+
+	#[tokio::test]
+	async fn relay_signal() -> Result<()> {
+		let (gw, _) = scripted(|cmd| match cmd {
+			"$KE,RDR,1" => "#RDR,1,1".into(),
+			"$KE,RDR,2" => "#RDR,2,0".into(),
+			_ => "#ERR".into(),
+		});
+		let gw = Arc::new(gw);
+
+		assert_eq!(Relay::new(gw.clone(), 1).signal().await?, Signal::High);
+		assert_eq!(Relay::new(gw, 2).signal().await?, Signal::Low);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn io_points_read_uniformly() -> Result<()> {
+		let (gw, received) = scripted(|cmd| match cmd {
+			"$KE,RDR,1" => "#RDR,1,1".into(),
+			"$KE,RD,2" => "#RD,2,0".into(),
+			"$KE,OUT,3" => "#OUT,3,1".into(),
+			"$KE,OUT,3,0" => "#OUT,OK".into(),
+			"$KE,ADC,1" => "#ADC,1,100".into(),
+			"$KE,ADC,2" => "#ADC,2,700".into(),
+			_ => "#ERR".into(),
+		});
+		let gw: Arc<dyn Gateway + Send + Sync> = Arc::new(gw);
+
+		let points: Vec<Box<dyn IoPoint>> = vec![
+			Box::new(Relay::new(gw.clone(), 1)),
+			Box::new(InputLine::new(gw.clone(), 2)),
+			Box::new(OutputChannel::new(gw.clone(), 3)),
+			Box::new(AnalogInput::new(gw.clone(), 2, 512)),
+			Box::new(AnalogInput::new(gw.clone(), 1, 512)),
+		];
+
+		let mut signals = Vec::new();
+		for point in &points {
+			signals.push(point.read().await?);
+		}
+		assert_eq!(
+			signals,
+			[
+				Signal::High,
+				Signal::Low,
+				Signal::High,
+				Signal::High,
+				Signal::Low
+			]
+		);
+
+		points[2].write(Signal::Low).await?;
+		assert_eq!(received.lock().unwrap().last().unwrap(), "$KE,OUT,3,0");
+		assert!(matches!(
+			points[1].write(Signal::High).await,
+			Err(Error::Unsupported)
+		));
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn relay_toggle_safe() -> Result<()> {
+		let (gw, received) = scripted(|cmd| match cmd {
+			"$KE,RDR,1" => "#RDR,1,1".into(),
+			"$KE,RDR,2" => "#RDR,2,0".into(),
+			_ => "#REL,OK".into(),
+		});
+		let gw = Arc::new(gw);
+
+		assert!(!Relay::new(gw.clone(), 1).toggle_safe().await?);
+		assert!(Relay::new(gw, 2).toggle_safe().await?);
+		assert_eq!(
+			*received.lock().unwrap(),
+			["$KE,RDR,1", "$KE,REL,1,0", "$KE,RDR,2", "$KE,REL,2,1"]
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn pulse_counter_rollover() {
+		let mut counter = PulseCounter::new(2, 65536);
+		assert_eq!(counter.observe(65000), 65000);
+
+		counter.handle(&Event::CounterOverflow { line: 1 });
+		assert_eq!(counter.total(), 65000);
+
+		counter.handle(&Event::CounterOverflow { line: 2 });
+		assert_eq!(counter.observe(10), 65546);
+		assert_eq!(counter.observe(20), 65556);
+	}
+
+	#[tokio::test]
+	async fn relay_toggle_and_read() -> Result<()> {
+		let (gw, received) = scripted(|cmd| match cmd {
+			"$KE,REL,2,2" => "#REL,OK".into(),
+			"$KE,RDR,2" => "#RDR,2,1".into(),
+			_ => "#ERR".into(),
+		});
+
+		assert!(Relay::new(Arc::new(gw), 2).toggle_and_read().await?);
+		assert_eq!(*received.lock().unwrap(), ["$KE,REL,2,2", "$KE,RDR,2"]);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn input_line_count() -> Result<()> {
+		let (gw, received) = scripted(|cmd| match cmd {
+			"$KE,CNT,4" => "#CNT,4,42".into(),
+			"$KE,CNT,4,RST" => "#CNT,4,OK".into(),
+			_ => "#ERR".into(),
+		});
+
+		let line = InputLine::new(Arc::new(gw), 4);
+		assert_eq!(line.count().await?, 42);
+		line.reset_count().await?;
+		assert_eq!(*received.lock().unwrap(), ["$KE,CNT,4", "$KE,CNT,4,RST"]);
+
+		Ok(())
+	}
+
+	#[test]
+	fn click_delay_from_duration() {
+		let delay = |millis| ClickDelay::from_duration(Duration::from_millis(millis));
+		assert_eq!(delay(500).unwrap(), ClickDelay::Millis100(5));
+		assert_eq!(delay(1540).unwrap(), ClickDelay::Millis100(15));
+		assert_eq!(delay(3000).unwrap(), ClickDelay::Seconds(3));
+		assert!(matches!(delay(20), Err(Error::InvalidPayload(_))));
+		assert!(matches!(
+			ClickDelay::from_duration(Duration::from_secs(100_000)),
+			Err(Error::InvalidPayload(_))
+		));
+	}
+
+	#[tokio::test]
+	async fn relay_click_for() -> Result<()> {
+		let (gw, received) = scripted(|_| "#REL,OK".into());
+		let relay = Relay::new(Arc::new(gw), 1);
+
+		relay.click_for(Duration::from_millis(500)).await?;
+		assert!(relay.click_for(Duration::ZERO).await.is_err());
+		assert_eq!(*received.lock().unwrap(), ["$KE,REL,1,1,.5"]);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn relay_set() -> Result<()> {
+		let (gw, received) = scripted(|_| "#REL,OK".into());
+		let relay = Relay::new(Arc::new(gw), 3);
+
+		relay.set(Signal::High).await?;
+		relay.set(Signal::Low).await?;
+		relay.set(true).await?;
+		assert_eq!(
+			*received.lock().unwrap(),
+			["$KE,REL,3,1", "$KE,REL,3,0", "$KE,REL,3,1"]
+		);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn relay_bank_set_all() -> Result<()> {
+		let (gw, received) = scripted(|_| "#WR,ALL,OK".into());
+		RelayBank::new(Arc::new(gw)).set_all(0b0110).await?;
+		assert_eq!(*received.lock().unwrap(), ["$KE,WR,ALL,6"]);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn radio_group_select() -> Result<()> {
+		let (gw, received) = scripted(|_| "#REL,OK".into());
+		let gw = Arc::new(gw);
+		let group = RadioGroup::new(gw.clone(), vec![1, 2, 3])?;
+
+		group.select(1).await?;
+		group.clear().await?;
+		assert_eq!(
+			*received.lock().unwrap(),
+			["$KE,REL,ALL,010x", "$KE,REL,ALL,000x"]
+		);
+		assert!(matches!(
+			group.select(3).await,
+			Err(Error::InvalidPayload(_))
+		));
+
+		for relays in [vec![0, 1], vec![1, 33], vec![2, 1, 2]] {
+			assert!(matches!(
+				RadioGroup::new(gw.clone(), relays),
+				Err(Error::InvalidPayload(_))
+			));
+		}
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn temp_sensor_tracks_extremes() -> Result<()> {
+		let (gw, _) = scripted(|_| "#ERR".into());
+		let sensor = TempSensor::new(Arc::new(gw), 1);
+
+		assert_eq!(sensor.extremes().await?, None);
+		for celsius in [21.5, -3.0, 25.25, 20.0] {
+			sensor.observe(celsius);
+		}
+		assert_eq!(sensor.extremes().await?, Some((-3.0, 25.25)));
+
+		sensor.reset_extremes().await?;
+		assert_eq!(sensor.extremes().await?, None);
+
+		Ok(())
+	}
+
+	/// A gateway whose event channel can be swapped out, like one that reconnected, and
+	/// whose relay commands time out a given number of times.
+	#[derive(Debug)]
+	struct Fake {
+		events: std::sync::Mutex<broadcast::Sender<Event>>,
+		signal: std::sync::Mutex<Signal>,
+		timeouts: AtomicU32,
+		relay_calls: AtomicU32,
+		/// Makes `line_signal` hang, like a device that stopped answering.
+		stalled: AtomicBool,
+		/// Makes `line_signal` time out.
+		read_timeout: AtomicBool,
+		relay_delay: std::sync::Mutex<Duration>,
+	}
+
+	impl Fake {
+		fn new(timeouts: u32) -> Arc<Self> {
+			Arc::new(Self {
+				events: std::sync::Mutex::new(broadcast::channel(16).0),
+				signal: std::sync::Mutex::new(Signal::Low),
+				timeouts: AtomicU32::new(timeouts),
+				relay_calls: AtomicU32::new(0),
+				stalled: AtomicBool::new(false),
+				read_timeout: AtomicBool::new(false),
+				relay_delay: std::sync::Mutex::new(Duration::ZERO),
+			})
+		}
+	}
+
+	#[async_trait]
+	impl Gateway for Fake {
+		fn subscibe(&self) -> EventReceiver {
+			self.events.lock().unwrap().subscribe()
+		}
+
+		async fn ping(&self) -> Result<()> {
+			Ok(())
+		}
+
+		async fn authorize(&self, _pwd: &str) -> Result<()> {
+			Ok(())
+		}
+
+		async fn cfg_event(&self, _kind: crate::EventKind, _enabled: bool) -> Result<()> {
+			Ok(())
+		}
+
+		async fn relay(&self, _: u32, _: RelayAction, _: Option<ClickDelay>) -> Result<()> {
+			self.relay_calls.fetch_add(1, Ordering::SeqCst);
+			let delay = *self.relay_delay.lock().unwrap();
+			tokio::time::sleep(delay).await;
+			let timeouts = &self.timeouts;
+			match timeouts.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)) {
+				Ok(_) => Err(Error::Timeout),
+				Err(_) => Ok(()),
+			}
+		}
+
+		async fn relay_status(&self, _relay: u32) -> Result<bool> {
+			Ok(false)
+		}
+
+		async fn line_signal(&self, _line: u32) -> Result<Signal> {
+			if self.stalled.load(Ordering::SeqCst) {
+				std::future::pending::<()>().await;
+			}
+			if self.read_timeout.load(Ordering::SeqCst) {
+				return Err(Error::Timeout);
+			}
+			Ok(*self.signal.lock().unwrap())
+		}
+	}
+
+	#[tokio::test]
+	async fn wait_signal_survives_reconnect() -> Result<()> {
+		let gw = Fake::new(0);
+		let input = Arc::new(InputLine::new(gw.clone(), 1));
+		let ein = |signal| Event::Ein { line: 1, signal };
+		let reconnect = || {
+			let (events, _) = broadcast::channel(16);
+			drop(std::mem::replace(&mut *gw.events.lock().unwrap(), events));
+		};
+
+		gw.events.lock().unwrap().send(ein(Signal::High)).unwrap();
+		assert!(input.wait_signal().await?.is_high());
+
+		// The line went low while the connection was down.
+		*gw.signal.lock().unwrap() = Signal::Low;
+		reconnect();
+		assert!(input.wait_signal().await?.is_low());
+
+		// Nothing was missed this time, so the next event is awaited.
+		let wait = tokio::spawn({
+			let input = input.clone();
+			async move { input.wait_signal().await }
+		});
+		tokio::task::yield_now().await;
+		reconnect();
+		tokio::task::yield_now().await;
+		gw.events.lock().unwrap().send(ein(Signal::High)).unwrap();
+		assert!(wait.await.unwrap()?.is_high());
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn wait_signal_resyncs_after_reconnect() -> Result<()> {
+		let device = FakeDevice::new();
+		let connect = {
+			let device = device.clone();
+			move || {
+				let stream = device.serve();
+				async move { Ok(stream) }
+			}
+		};
+		let gw = StreamGateway::connect_reconnecting(connect, &GatewayOptions::new()).await?;
+		let gw = Arc::new(gw);
+		gw.cfg_event(EventKind::Ein, true).await?;
+		let input = InputLine::new(gw.clone(), 1);
+
+		device.set_input(1, Signal::High)?;
+		assert!(input.wait_signal().await?.is_high());
+
+		// The line goes low while the connection is down, so no event reports it.
+		device.disconnect();
+		device.set_input(1, Signal::Low)?;
+		let wait = tokio::time::timeout(Duration::from_secs(1), input.wait_signal()).await;
+		assert!(wait.expect("missed edge not returned")?.is_low());
+
+		// Events are back on for the new connection.
+		device.set_input(1, Signal::High)?;
+		assert!(input.wait_signal().await?.is_high());
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn wait_rising_on_edges_only() -> Result<()> {
+		let gw = Fake::new(0);
+		let input = InputLine::new(gw.clone(), 1);
+		let events = gw.events.lock().unwrap().clone();
+		for signal in [Signal::Low, Signal::High, Signal::High, Signal::Low] {
+			events.send(Event::Ein { line: 1, signal }).unwrap();
+		}
+
+		input.wait_rising().await?;
+		let wait = tokio::time::timeout(Duration::from_millis(10), input.wait_rising()).await;
+		assert!(wait.is_err());
+
+		events
+			.send(Event::Ein {
+				line: 1,
+				signal: Signal::High,
+			})
+			.unwrap();
+		input.wait_rising().await?;
+		events
+			.send(Event::Ein {
+				line: 1,
+				signal: Signal::Low,
+			})
+			.unwrap();
+		input.wait_falling().await?;
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn wait_signal_debounced() -> Result<()> {
+		let gw = Fake::new(0);
+		let input = InputLine::new(gw.clone(), 1);
+		let events = gw.events.lock().unwrap().clone();
+
+		let sender = tokio::spawn(async move {
+			for i in 0..7 {
+				let signal = Signal::from_bool(i % 2 == 1);
+				events.send(Event::Ein { line: 1, signal }).unwrap();
+				tokio::time::sleep(Duration::from_millis(5)).await;
+			}
+			events
+		});
+
+		let window = Duration::from_millis(40);
+		assert_eq!(input.wait_signal_debounced(window).await?, Signal::Low);
+		let events = sender.await.unwrap();
+		let wait = tokio::time::timeout(window, input.wait_signal()).await;
+		assert!(wait.is_err());
+
+		events
+			.send(Event::Ein {
+				line: 1,
+				signal: Signal::High,
+			})
+			.unwrap();
+		assert_eq!(input.wait_signal_debounced(window).await?, Signal::High);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn wait_signal_reports_lag() -> Result<()> {
+		let gw = Fake::new(0);
+		let input = InputLine::new(gw.clone(), 1);
+
+		let events = gw.events.lock().unwrap().clone();
+		for i in 0..20 {
+			let signal = Signal::from_bool(i % 2 == 1);
+			events.send(Event::Ein { line: 1, signal }).unwrap();
+		}
+
+		assert!(matches!(
+			input.wait_signal().await,
+			Err(Error::EventsLagged(4))
+		));
+		// Resyncs, then picks up the oldest event still queued.
+		assert_eq!(input.wait_signal().await?, Signal::Low);
+		assert_eq!(input.wait_signal().await?, Signal::High);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn wait_signal_passes_on_resync_errors() -> Result<()> {
+		let gw = Fake::new(0);
+		let input = InputLine::new(gw.clone(), 1);
+
+		let events = gw.events.lock().unwrap().clone();
+		for i in 0..20 {
+			let signal = Signal::from_bool(i % 2 == 1);
+			events.send(Event::Ein { line: 1, signal }).unwrap();
+		}
+		assert!(matches!(
+			input.wait_signal().await,
+			Err(Error::EventsLagged(_))
+		));
+
+		// A failed read is no reason to give up on the connection.
+		gw.read_timeout.store(true, Ordering::SeqCst);
+		assert!(matches!(input.wait_signal().await, Err(Error::Timeout)));
+		gw.read_timeout.store(false, Ordering::SeqCst);
+		assert_eq!(input.wait_signal().await?, Signal::Low);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn analog_input_reports_lag() -> Result<()> {
+		use futures::StreamExt;
+
+		let gw = Fake::new(0);
+		let input = AnalogInput::new(gw.clone(), 1, 1000);
+		let mut changes = Box::pin(input.changes());
+
+		let events = gw.events.lock().unwrap().clone();
+		for value in 0..20 {
+			events.send(Event::Adc { channel: 1, value }).unwrap();
+		}
+		assert!(matches!(
+			changes.next().await,
+			Some(Err(Error::EventsLagged(4)))
+		));
+		assert_eq!(changes.next().await.transpose()?, Some(4));
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn analog_input_changes() -> Result<()> {
+		use futures::StreamExt;
+
+		let gw = Fake::new(0);
+		let input = AnalogInput::new(gw.clone(), 2, 1000);
+		let mut changes = Box::pin(input.changes());
+
+		let events = gw.events.lock().unwrap().clone();
+		events
+			.send(Event::Adc {
+				channel: 1,
+				value: 7,
+			})
+			.unwrap();
+		events
+			.send(Event::Adc {
+				channel: 2,
+				value: 1500,
+			})
+			.unwrap();
+		assert_eq!(changes.next().await.transpose()?, Some(1500));
+
+		drop(events);
+		drop(std::mem::replace(
+			&mut *gw.events.lock().unwrap(),
+			broadcast::channel(16).0,
+		));
+		assert!(changes.next().await.is_none());
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn relay_retries_transient_errors() -> Result<()> {
+		let gw = Fake::new(1);
+		let relay = Relay::new(gw.clone(), 1).with_retry(RetryPolicy::new(3, Duration::ZERO));
+		relay.on().await?;
+		assert_eq!(gw.relay_calls.load(Ordering::SeqCst), 2);
+
+		let gw = Fake::new(1);
+		let relay = Relay::new(gw.clone(), 1);
+		assert!(matches!(relay.on().await, Err(Error::Timeout)));
+		assert_eq!(gw.relay_calls.load(Ordering::SeqCst), 1);
+
+		let gw = Fake::new(1);
+		let relay = Relay::new(gw.clone(), 1).with_retry(RetryPolicy::new(3, Duration::ZERO));
+		assert!(matches!(relay.toggle().await, Err(Error::Timeout)));
+		assert_eq!(gw.relay_calls.load(Ordering::SeqCst), 1);
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn wait_signal_is_cancel_safe() -> Result<()> {
+		let gw = Fake::new(0);
+		let input = InputLine::new(gw.clone(), 1);
+		let events = gw.events.lock().unwrap().clone();
+
+		let sender = tokio::spawn(async move {
+			for i in 0..20 {
+				let signal = Signal::from_bool(i % 2 == 0);
+				events.send(Event::Ein { line: 1, signal }).unwrap();
+				tokio::task::yield_now().await;
+			}
+		});
+
+		let mut received = Vec::new();
+		while received.len() < 20 {
+			tokio::select! {
+				res = input.wait_signal() => received.push(res?),
+				_ = tokio::task::yield_now() => (),
+			}
+		}
+		let expected: Vec<_> = (0..20).map(|i| Signal::from_bool(i % 2 == 0)).collect();
+		assert_eq!(received, expected);
+		sender.await.unwrap();
+
+		// Cancelled while reading the level after a reconnect.
+		gw.stalled.store(true, Ordering::SeqCst);
+		*gw.signal.lock().unwrap() = Signal::High;
+		drop(std::mem::replace(
+			&mut *gw.events.lock().unwrap(),
+			broadcast::channel(16).0,
+		));
+		let wait = tokio::time::timeout(Duration::from_millis(10), input.wait_signal()).await;
+		assert!(wait.is_err());
+
+		// The next call finishes the resync and reports the edge missed in between.
+		gw.stalled.store(false, Ordering::SeqCst);
+		assert!(input.wait_signal().await?.is_high());
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn relay_tracks_latency() -> Result<()> {
+		let gw = Fake::new(0);
+		let relay = Relay::new(gw.clone(), 1);
+		assert_eq!(relay.last_latency(), None);
+		assert_eq!(relay.avg_latency(), None);
+
+		*gw.relay_delay.lock().unwrap() = Duration::from_millis(40);
+		relay.on().await?;
+		let first = relay.last_latency().unwrap();
+		assert!(first >= Duration::from_millis(40));
+
+		*gw.relay_delay.lock().unwrap() = Duration::ZERO;
+		relay.off().await?;
+		let second = relay.last_latency().unwrap();
+		assert!(second < Duration::from_millis(40));
+
+		let avg = relay.avg_latency().unwrap();
+		assert!(second < avg && avg < first);
+
+		Ok(())
+	}
 }
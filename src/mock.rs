@@ -0,0 +1,188 @@
+use crate::ClickDelay;
+use crate::Event;
+use crate::EventKind;
+use crate::EventReceiver;
+use crate::Gateway;
+use crate::RelayAction;
+use crate::Result;
+use crate::Signal;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+/// A call made to a [`MockGateway`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockCall {
+	Ping,
+	Authorize(String),
+	CfgEvent(EventKind, bool),
+	Relay {
+		relay: u32,
+		action: RelayAction,
+		delay: Option<ClickDelay>,
+	},
+	RelayStatus(u32),
+	LineSignal(u32),
+}
+
+/// An in-memory [`Gateway`] for unit tests of code built on this crate.
+///
+/// Relays and inputs are plain state: relays start off and inputs low until set. Relay
+/// commands change the state right away, with click delays ignored. Events are only sent
+/// by [`emit`](Self::emit), and every call is recorded for [`calls`](Self::calls).
+///
+/// Unlike [`FakeDevice`](crate::FakeDevice) no wire protocol is involved, so only the
+/// basic methods are implemented; the others answer with
+/// [`Error::Unsupported`](crate::Error::Unsupported).
+#[derive(Debug)]
+pub struct MockGateway {
+	state: Mutex<MockState>,
+	events: broadcast::Sender<Event>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+	relays: HashMap<u32, bool>,
+	inputs: HashMap<u32, Signal>,
+	calls: Vec<MockCall>,
+}
+
+impl Default for MockGateway {
+	fn default() -> Self {
+		Self {
+			state: Default::default(),
+			events: broadcast::channel(1024).0,
+		}
+	}
+}
+
+impl MockGateway {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn set_relay(&self, relay: u32, on: bool) {
+		self.state.lock().unwrap().relays.insert(relay, on);
+	}
+
+	pub fn relay_state(&self, relay: u32) -> bool {
+		self.state.lock().unwrap().relays.get(&relay) == Some(&true)
+	}
+
+	/// Sets the level [`line_signal`](Gateway::line_signal) returns. No event is sent.
+	pub fn set_input(&self, line: u32, signal: Signal) {
+		self.state.lock().unwrap().inputs.insert(line, signal);
+	}
+
+	/// Sends `event` to the subscribers, returning whether there were any.
+	pub fn emit(&self, event: Event) -> bool {
+		self.events.send(event).is_ok()
+	}
+
+	/// Calls made so far, oldest first.
+	pub fn calls(&self) -> Vec<MockCall> {
+		self.state.lock().unwrap().calls.clone()
+	}
+
+	fn record(&self, call: MockCall) {
+		self.state.lock().unwrap().calls.push(call);
+	}
+}
+
+#[async_trait]
+impl Gateway for MockGateway {
+	fn subscibe(&self) -> EventReceiver {
+		self.events.subscribe()
+	}
+
+	async fn ping(&self) -> Result<()> {
+		self.record(MockCall::Ping);
+		Ok(())
+	}
+
+	async fn authorize(&self, pwd: &str) -> Result<()> {
+		self.record(MockCall::Authorize(pwd.to_owned()));
+		Ok(())
+	}
+
+	async fn cfg_event(&self, kind: EventKind, enabled: bool) -> Result<()> {
+		self.record(MockCall::CfgEvent(kind, enabled));
+		Ok(())
+	}
+
+	async fn relay(&self, relay: u32, action: RelayAction, delay: Option<ClickDelay>) -> Result<()> {
+		self.record(MockCall::Relay {
+			relay,
+			action,
+			delay,
+		});
+		let mut state = self.state.lock().unwrap();
+		let on = state.relays.entry(relay).or_default();
+		*on = match action {
+			RelayAction::On => true,
+			RelayAction::Off => false,
+			RelayAction::Toggle => !*on,
+		};
+		Ok(())
+	}
+
+	async fn relay_status(&self, relay: u32) -> Result<bool> {
+		self.record(MockCall::RelayStatus(relay));
+		Ok(self.relay_state(relay))
+	}
+
+	async fn line_signal(&self, line: u32) -> Result<Signal> {
+		self.record(MockCall::LineSignal(line));
+		let state = self.state.lock().unwrap();
+		Ok(state.inputs.get(&line).copied().unwrap_or(Signal::Low))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::InputLine;
+	use crate::Relay;
+	use std::sync::Arc;
+
+	#[tokio::test]
+	async fn mock_gateway() -> Result<()> {
+		let mock = Arc::new(MockGateway::new());
+		let relay = Relay::new(mock.clone(), 1);
+
+		relay.on().await?;
+		assert!(mock.relay_state(1));
+		relay.toggle().await?;
+		assert!(!relay.status().await?);
+
+		mock.set_input(2, Signal::High);
+		let input = InputLine::new(mock.clone(), 2);
+		assert_eq!(input.read_signal().await?, Signal::High);
+		assert!(mock.emit(Event::Ein {
+			line: 2,
+			signal: Signal::Low,
+		}));
+		assert_eq!(input.wait_signal().await?, Signal::Low);
+
+		assert_eq!(
+			mock.calls(),
+			[
+				MockCall::Relay {
+					relay: 1,
+					action: RelayAction::On,
+					delay: None,
+				},
+				MockCall::Relay {
+					relay: 1,
+					action: RelayAction::Toggle,
+					delay: None,
+				},
+				MockCall::RelayStatus(1),
+				MockCall::LineSignal(2),
+			]
+		);
+
+		Ok(())
+	}
+}
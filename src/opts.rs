@@ -0,0 +1,188 @@
+use crate::Audit;
+use crate::Capabilities;
+use crate::Error;
+use crate::EventKind;
+use crate::Result;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Settings applied by [`StreamGateway::open`](crate::StreamGateway::open).
+#[derive(Debug, Clone)]
+pub struct GatewayOptions {
+	pub(crate) password: Option<String>,
+	pub(crate) events: Vec<EventKind>,
+	pub(crate) connect_timeout: Option<Duration>,
+	pub(crate) command_timeout: Option<Duration>,
+	pub(crate) nodelay: bool,
+	pub(crate) keepalive: Option<Duration>,
+	pub(crate) label: Option<String>,
+	pub(crate) capabilities: Capabilities,
+	pub(crate) capacity: usize,
+	pub(crate) raw_tap: bool,
+	pub(crate) event_parts: bool,
+	pub(crate) lenient: bool,
+	pub(crate) strict: bool,
+	pub(crate) event_interval: Option<Duration>,
+	pub(crate) audit: Option<Arc<dyn Audit>>,
+}
+
+impl Default for GatewayOptions {
+	fn default() -> Self {
+		Self {
+			password: None,
+			events: Vec::new(),
+			connect_timeout: None,
+			command_timeout: None,
+			nodelay: false,
+			keepalive: None,
+			label: None,
+			capabilities: Capabilities::default(),
+			capacity: 1024,
+			raw_tap: false,
+			event_parts: false,
+			lenient: false,
+			strict: false,
+			event_interval: None,
+			audit: None,
+		}
+	}
+}
+
+impl GatewayOptions {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Password to authorize with right after connecting.
+	pub fn with_password(mut self, pwd: impl Into<String>) -> Self {
+		self.password = Some(pwd.into());
+		self
+	}
+
+	/// Event kind to enable right after connecting (and authorizing).
+	pub fn with_event(mut self, kind: EventKind) -> Self {
+		self.events.push(kind);
+		self
+	}
+
+	/// Upper bound for establishing the TCP connection.
+	pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+		self.connect_timeout = Some(timeout);
+		self
+	}
+
+	/// How long a command waits for its reply before failing with
+	/// [`Error::Timeout`](crate::Error::Timeout). Only the call fails; the connection stays
+	/// up, and a reply that arrives after the deadline is dropped. Commands wait forever by
+	/// default.
+	///
+	/// Replies carry no command tag, so a frame that starts like the late reply (or is an
+	/// `#ERR`) is taken to be it. Any other frame means the device never answered, and goes
+	/// to the following command. Raw commands have no known reply and take the next frame
+	/// either way.
+	pub fn with_command_timeout(mut self, timeout: Duration) -> Self {
+		self.command_timeout = Some(timeout);
+		self
+	}
+
+	/// Sets `TCP_NODELAY` on the connection, so short commands go out without waiting to be
+	/// coalesced. Worth it for interactive relay control, where Nagle's algorithm can add
+	/// tens of milliseconds.
+	pub fn with_nodelay(mut self, enabled: bool) -> Self {
+		self.nodelay = enabled;
+		self
+	}
+
+	/// Enables OS-level TCP keepalive, probing the connection after it has been idle for
+	/// `idle`.
+	pub fn with_tcp_keepalive(mut self, idle: Duration) -> Self {
+		self.keepalive = Some(idle);
+		self
+	}
+
+	/// Name of the gateway's reader task as shown by `tokio-console`.
+	///
+	/// Defaults to the peer address when connecting with
+	/// [`StreamGateway::open`](crate::StreamGateway::open). Only takes effect with the
+	/// `tracing` feature on a `tokio_unstable` build.
+	pub fn with_label(mut self, label: impl Into<String>) -> Self {
+		self.label = Some(label.into());
+		self
+	}
+
+	/// Optional firmware features the device is known to support.
+	pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+		self.capabilities = capabilities;
+		self
+	}
+
+	/// How many events the gateway buffers for each subscriber before the slowest one starts
+	/// lagging. Must be at least 1; defaults to 1024.
+	pub fn with_event_capacity(mut self, capacity: usize) -> Self {
+		self.capacity = capacity;
+		self
+	}
+
+	/// Keeps a copy of every line received from the module, before it is split into
+	/// fields, for [`StreamGateway::raw_lines`](crate::StreamGateway::raw_lines).
+	///
+	/// Meant for protocol debugging; off by default to spare the extra copy per line.
+	pub fn with_raw_tap(mut self, enabled: bool) -> Self {
+		self.raw_tap = enabled;
+		self
+	}
+
+	/// Keeps the fields of every event next to the parsed [`Event`](crate::Event), for
+	/// [`StreamGateway::subscribe_with_parts`](crate::StreamGateway::subscribe_with_parts).
+	///
+	/// Meant for bridges that forward events verbatim. Each buffered event then holds its
+	/// fields as separate strings as well, so this is off by default.
+	pub fn with_event_parts(mut self, enabled: bool) -> Self {
+		self.event_parts = enabled;
+		self
+	}
+
+	/// Makes typed methods return [`Error::Unrecognized`] with the reply instead of
+	/// [`Error::UnknownMessage`] or [`Error::UnexpectedHeader`], so replies from newer
+	/// firmware can be inspected. `#ERR` is still reported as [`Error::SyntaxError`].
+	pub fn with_lenient(mut self, enabled: bool) -> Self {
+		self.lenient = enabled;
+		self
+	}
+
+	/// Rejects replies that carry more fields than the command defines with
+	/// [`Error::UnexpectedMessage`], for protocol conformance testing. By default such a
+	/// reply isn't recognized, like any other, and fails with [`Error::UnknownMessage`] or
+	/// is passed on by [lenient](Self::with_lenient) gateways.
+	pub fn with_strict(mut self, enabled: bool) -> Self {
+		self.strict = enabled;
+		self
+	}
+
+	/// Minimum time between two `EIN` events of the same line. Events that follow sooner
+	/// are dropped before they reach any subscriber.
+	///
+	/// A coarse guard against chattering inputs, off by default. The level an input settles
+	/// on may be among the dropped events, so read it with
+	/// [`Gateway::line_signal`](crate::Gateway::line_signal) where that matters.
+	pub fn with_event_interval(mut self, interval: Duration) -> Self {
+		self.event_interval = Some(interval);
+		self
+	}
+
+	/// Reports every state-changing command to `audit` before it is sent.
+	pub fn with_audit(mut self, audit: Arc<dyn Audit>) -> Self {
+		self.audit = Some(audit);
+		self
+	}
+
+	pub(crate) fn validate(&self) -> Result<()> {
+		if self.capacity == 0 {
+			return Err(Error::InvalidPayload(
+				"The event capacity must be at least 1".into(),
+			));
+		}
+
+		Ok(())
+	}
+}
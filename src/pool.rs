@@ -0,0 +1,156 @@
+use crate::GatewayOptions;
+use crate::Result;
+use crate::StreamGateway;
+use std::ops::Deref;
+use std::sync::Arc;
+use std::sync::Mutex;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::sync::Semaphore;
+
+/// A bounded set of sessions to one module, shared by many tasks.
+///
+/// Laurent modules accept only a few TCP connections at a time. The pool opens sessions
+/// lazily with [`StreamGateway::open`], never more than `max_size` of them, and hands them
+/// out one caller at a time. Callers beyond that wait in line for a session to be returned.
+#[derive(Debug, Clone)]
+pub struct GatewayPool {
+	inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+	addr: String,
+	opts: GatewayOptions,
+	idle: Mutex<Vec<StreamGateway>>,
+	permits: Arc<Semaphore>,
+}
+
+impl GatewayPool {
+	/// Creates an empty pool for the module at `addr`. `max_size` is raised to 1 if it is 0.
+	pub fn new(addr: impl Into<String>, opts: GatewayOptions, max_size: usize) -> Self {
+		let inner = Inner {
+			addr: addr.into(),
+			opts,
+			idle: Mutex::new(Vec::new()),
+			permits: Arc::new(Semaphore::new(max_size.max(1))),
+		};
+
+		Self {
+			inner: Arc::new(inner),
+		}
+	}
+
+	/// Takes a session out of the pool, opening one if none is idle and the pool isn't full.
+	///
+	/// Waiting callers are served in the order they arrived. The session goes back to the
+	/// pool when the returned guard is dropped, unless its connection has closed.
+	pub async fn checkout(&self) -> Result<PooledGateway> {
+		let permit = self
+			.inner
+			.permits
+			.clone()
+			.acquire_owned()
+			.await
+			.expect("The pool semaphore is never closed");
+
+		let idle = self.inner.idle.lock().unwrap().pop();
+		let gw = match idle {
+			Some(gw) => gw,
+			None => StreamGateway::open(self.inner.addr.as_str(), self.inner.opts.clone()).await?,
+		};
+
+		Ok(PooledGateway {
+			gw: Some(gw),
+			pool: self.inner.clone(),
+			_permit: permit,
+		})
+	}
+
+	/// Number of sessions waiting in the pool to be checked out.
+	pub fn idle(&self) -> usize {
+		self.inner.idle.lock().unwrap().len()
+	}
+}
+
+/// A session checked out of a [`GatewayPool`].
+#[derive(Debug)]
+pub struct PooledGateway {
+	gw: Option<StreamGateway>,
+	pool: Arc<Inner>,
+	// Released after `drop` has put the session back.
+	_permit: OwnedSemaphorePermit,
+}
+
+impl Deref for PooledGateway {
+	type Target = StreamGateway;
+
+	fn deref(&self) -> &StreamGateway {
+		self.gw.as_ref().expect("The session is only taken on drop")
+	}
+}
+
+impl Drop for PooledGateway {
+	fn drop(&mut self) {
+		if let Some(gw) = self.gw.take().filter(|gw| !gw.is_closed()) {
+			self.pool.idle.lock().unwrap().push(gw);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::Error;
+	use crate::Gateway;
+	use std::sync::atomic::AtomicUsize;
+	use std::sync::atomic::Ordering;
+	use std::time::Duration;
+	use tokio::io::AsyncBufReadExt;
+	use tokio::io::AsyncWriteExt;
+	use tokio::io::BufReader;
+	use tokio::net::TcpListener;
+
+	#[tokio::test]
+	async fn pool_bounds_sessions() -> Result<()> {
+		let listener = TcpListener::bind("127.0.0.1:0").await?;
+		let addr = listener.local_addr()?;
+		let sessions = Arc::new(AtomicUsize::new(0));
+
+		tokio::spawn({
+			let sessions = sessions.clone();
+			async move {
+				while let Ok((stream, _)) = listener.accept().await {
+					sessions.fetch_add(1, Ordering::SeqCst);
+					tokio::spawn(async move {
+						let (rd, mut wr) = stream.into_split();
+						let mut lines = BufReader::new(rd).lines();
+						while let Ok(Some(_)) = lines.next_line().await {
+							let _ = wr.write_all(b"#OK\r\n").await;
+						}
+					});
+				}
+			}
+		});
+
+		let pool = GatewayPool::new(addr.to_string(), GatewayOptions::new(), 2);
+		let tasks: Vec<_> = (0..6)
+			.map(|_| {
+				let pool = pool.clone();
+				tokio::spawn(async move {
+					let gw = pool.checkout().await?;
+					gw.ping().await?;
+					tokio::time::sleep(Duration::from_millis(10)).await;
+					Ok::<_, Error>(())
+				})
+			})
+			.collect();
+
+		for task in tasks {
+			task.await.unwrap()?;
+		}
+		assert_eq!(sessions.load(Ordering::SeqCst), 2);
+		assert_eq!(pool.idle(), 2);
+
+		Ok(())
+	}
+}
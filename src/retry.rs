@@ -0,0 +1,57 @@
+use crate::Result;
+use std::future::Future;
+use std::time::Duration;
+
+/// How often a handle re-issues a command that failed with a transient error.
+///
+/// The default policy never retries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+	attempts: u32,
+	backoff: Duration,
+}
+
+impl RetryPolicy {
+	/// Tries a command up to `attempts` times, waiting `backoff` between tries.
+	pub fn new(attempts: u32, backoff: Duration) -> Self {
+		Self {
+			attempts: attempts.max(1),
+			backoff,
+		}
+	}
+
+	pub fn none() -> Self {
+		Self::new(1, Duration::ZERO)
+	}
+
+	pub fn attempts(&self) -> u32 {
+		self.attempts
+	}
+
+	pub fn backoff(&self) -> Duration {
+		self.backoff
+	}
+
+	pub(crate) async fn run<T, F, Fut>(&self, mut op: F) -> Result<T>
+	where
+		F: FnMut() -> Fut,
+		Fut: Future<Output = Result<T>>,
+	{
+		let mut attempt = 1;
+		loop {
+			match op().await {
+				Err(err) if err.is_transient() && attempt < self.attempts => {
+					attempt += 1;
+					tokio::time::sleep(self.backoff).await;
+				}
+				res => return res,
+			}
+		}
+	}
+}
+
+impl Default for RetryPolicy {
+	fn default() -> Self {
+		Self::none()
+	}
+}
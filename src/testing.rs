@@ -0,0 +1,50 @@
+use crate::GatewayOptions;
+use crate::StreamGateway;
+use std::sync::Arc;
+use std::sync::Mutex;
+use tokio::io::AsyncBufReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::io::BufReader;
+
+/// Commands received by a [`scripted`] device, in arrival order.
+pub type Received = Arc<Mutex<Vec<String>>>;
+
+/// Connects a gateway to an in-memory device that answers each command line with
+/// `reply(line)`. An empty reply sends nothing back.
+pub fn scripted<F>(reply: F) -> (StreamGateway, Received)
+where
+	F: Fn(&str) -> String + Send + 'static,
+{
+	scripted_with(&GatewayOptions::default(), reply)
+}
+
+/// Like [`scripted`], with the gateway connected using `opts`.
+pub fn scripted_with<F>(opts: &GatewayOptions, reply: F) -> (StreamGateway, Received)
+where
+	F: Fn(&str) -> String + Send + 'static,
+{
+	let (client, device) = tokio::io::duplex(1024);
+	let received = Received::default();
+
+	let log = received.clone();
+	tokio::spawn(async move {
+		let (rd, mut wr) = tokio::io::split(device);
+		let mut lines = BufReader::new(rd).lines();
+
+		while let Ok(Some(line)) = lines.next_line().await {
+			let answer = reply(&line);
+			log.lock().unwrap().push(line);
+			if answer.is_empty() {
+				continue;
+			}
+
+			let answer = format!("{answer}\r\n");
+			if wr.write_all(answer.as_bytes()).await.is_err() {
+				break;
+			}
+		}
+	});
+
+	let gw = StreamGateway::connect_with(client, opts).expect("Invalid gateway options");
+	(gw, received)
+}